@@ -0,0 +1,391 @@
+// ANSI escape sequences for the text consoles
+// the VGA and the framebuffer console run their output through the same parser, so the colors
+// (SGR) and cursor movements of ESC[ sequences look the same on both
+
+use vga_buffer::Color;
+
+// ANSI color numbers (black, red, green, yellow, blue, magenta, cyan, white) as VGA colors
+const ANSI_TO_VGA: [u8; 8] = [
+    Color::Black as u8, Color::Red as u8, Color::Green as u8, Color::Brown as u8,
+    Color::Blue as u8, Color::Magenta as u8, Color::Cyan as u8, Color::LightGray as u8,
+];
+
+const ESCAPE: u8 = 0x1b;
+const MAX_CSI_PARAMS: usize = 8;
+
+// numeric parameters of a CSI sequence, ESC[1;31m has the parameters 1 and 31
+#[derive(Debug, Clone, Copy)]
+pub struct CsiParams {
+    values: [usize; MAX_CSI_PARAMS],
+    count: usize,
+}
+
+impl CsiParams {
+    // missing and 0 parameters get the default value
+    pub fn get(&self, index: usize, default: usize) -> usize {
+        if index < self.count && self.values[index] != 0 {
+            self.values[index]
+        } else {
+            default
+        }
+    }
+}
+
+// what the writer has to do after a byte went through the parser
+#[derive(Debug, Clone, Copy)]
+pub enum Action {
+    Print(u8),
+    Csi(u8, CsiParams),
+    SaveCursor,
+    RestoreCursor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ParserState {
+    Ground,
+    // after ESC
+    Escape,
+    // after ESC [
+    Csi,
+}
+
+// state machine that splits the output into printable bytes and escape sequences
+pub struct EscapeParser {
+    state: ParserState,
+    params: CsiParams,
+    // more than MAX_CSI_PARAMS parameters, the digits of the rest are dropped
+    params_full: bool,
+}
+
+impl EscapeParser {
+    pub const fn new() -> EscapeParser {
+        EscapeParser {
+            state: ParserState::Ground,
+            params: CsiParams { values: [0; MAX_CSI_PARAMS], count: 0 },
+            params_full: false,
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Option<Action> {
+        match self.state {
+            ParserState::Ground if byte == ESCAPE => {
+                self.state = ParserState::Escape;
+                None
+            }
+            ParserState::Ground => Some(Action::Print(byte)),
+            ParserState::Escape => {
+                self.state = ParserState::Ground;
+                match byte {
+                    b'[' => {
+                        self.state = ParserState::Csi;
+                        self.params = CsiParams { values: [0; MAX_CSI_PARAMS], count: 0 };
+                        self.params_full = false;
+                        None
+                    }
+                    // DECSC and DECRC
+                    b'7' => Some(Action::SaveCursor),
+                    b'8' => Some(Action::RestoreCursor),
+                    _ => None,
+                }
+            }
+            ParserState::Csi => {
+                match byte {
+                    b'0'...b'9' if self.params_full => None,
+                    b'0'...b'9' => {
+                        if self.params.count == 0 {
+                            self.params.count = 1;
+                        }
+                        let value = &mut self.params.values[self.params.count - 1];
+                        *value = value.saturating_mul(10).saturating_add((byte - b'0') as usize);
+                        None
+                    }
+                    b';' => {
+                        if self.params.count == 0 {
+                            self.params.count = 1;
+                        }
+                        // further parameters are ignored
+                        if self.params.count < MAX_CSI_PARAMS {
+                            self.params.count += 1;
+                        } else {
+                            self.params_full = true;
+                        }
+                        None
+                    }
+                    // final byte
+                    0x40...0x7e => {
+                        self.state = ParserState::Ground;
+                        Some(Action::Csi(byte, self.params))
+                    }
+                    // private markers (?) and intermediate bytes
+                    0x20...0x3f => None,
+                    // anything else aborts the sequence
+                    _ => {
+                        self.state = ParserState::Ground;
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    /// True if no escape sequence is being parsed, the writers only show
+    /// characters outside of ASCII then.
+    pub fn in_ground_state(&self) -> bool {
+        self.state == ParserState::Ground
+    }
+
+    /// Drops the escape sequence that is being parsed.
+    pub fn abort(&mut self) {
+        self.state = ParserState::Ground;
+    }
+}
+
+/// The colors set by SGR sequences, as VGA color numbers (0-15).
+#[derive(Debug, Clone, Copy)]
+pub struct Rendition {
+    pub foreground: u8,
+    pub background: u8,
+    // colors after reset (ESC[0m, ESC[39m, ESC[49m)
+    default_foreground: u8,
+    default_background: u8,
+    // bold (ESC[1m) makes the foreground color bright
+    bold: bool,
+    // the foreground is one of the bright colors 90-97, bold off (ESC[22m) keeps it bright
+    bright_foreground: bool,
+}
+
+impl Rendition {
+    pub const fn new(foreground: Color, background: Color) -> Rendition {
+        Rendition {
+            foreground: foreground as u8,
+            background: background as u8,
+            default_foreground: foreground as u8,
+            default_background: background as u8,
+            bold: false,
+            bright_foreground: false,
+        }
+    }
+
+    // SGR, sets the colors. ESC[m is the same as ESC[0m
+    fn select_graphic_rendition(&mut self, params: &CsiParams) {
+        let count = ::core::cmp::max(params.count, 1);
+        for &param in &params.values[..count] {
+            match param {
+                0 => *self = Rendition {
+                    foreground: self.default_foreground,
+                    background: self.default_background,
+                    bold: false,
+                    bright_foreground: false,
+                    ..*self
+                },
+                1 => {
+                    self.bold = true;
+                    self.foreground |= 0x08;
+                }
+                22 => {
+                    self.bold = false;
+                    // only the intensity that bold added goes away
+                    if !self.bright_foreground {
+                        self.foreground &= 0x07;
+                    }
+                }
+                30...37 => {
+                    self.bright_foreground = false;
+                    let bright = if self.bold { 0x08 } else { 0 };
+                    self.foreground = ANSI_TO_VGA[param - 30] | bright;
+                }
+                39 => {
+                    self.bright_foreground = false;
+                    self.foreground = self.default_foreground;
+                }
+                40...47 => self.background = ANSI_TO_VGA[param - 40],
+                49 => self.background = self.default_background,
+                90...97 => {
+                    self.bright_foreground = true;
+                    self.foreground = ANSI_TO_VGA[param - 90] | 0x08;
+                }
+                100...107 => self.background = ANSI_TO_VGA[param - 100] | 0x08,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A text screen that the escape sequences act on. Positions are
+/// (row, column) and 0-based.
+pub trait Terminal {
+    /// The size in characters, (columns, rows).
+    fn size(&self) -> (usize, usize);
+    fn cursor(&self) -> (usize, usize);
+    fn set_cursor(&mut self, row: usize, column: usize);
+    /// The position stored by ESC[s and ESC7.
+    fn saved_cursor(&mut self) -> &mut (usize, usize);
+    fn rendition(&mut self) -> &mut Rendition;
+    /// Clears the columns `start..end` of the row with the current background.
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize);
+}
+
+/// Runs an action of the parser on the terminal. `Action::Print` is left to
+/// the caller.
+pub fn execute<T: Terminal>(terminal: &mut T, action: Action) {
+    match action {
+        Action::Print(_) => {}
+        Action::Csi(final_byte, params) => execute_csi(terminal, final_byte, &params),
+        Action::SaveCursor => {
+            let cursor = terminal.cursor();
+            *terminal.saved_cursor() = cursor;
+        }
+        Action::RestoreCursor => {
+            let (row, col) = *terminal.saved_cursor();
+            terminal.set_cursor(row, col);
+        }
+    }
+}
+
+// runs a complete CSI sequence (ESC [ params final_byte)
+fn execute_csi<T: Terminal>(terminal: &mut T, final_byte: u8, params: &CsiParams) {
+    let (width, height) = terminal.size();
+    let (row, col) = terminal.cursor();
+    let col = ::core::cmp::min(col, width - 1);
+    match final_byte {
+        // CUP, the parameters are 1-based
+        b'H' | b'f' => {
+            terminal.set_cursor(::core::cmp::min(params.get(0, 1), height) - 1,
+                                ::core::cmp::min(params.get(1, 1), width) - 1);
+        }
+        // CUU, CUD, CUF, CUB, the cursor stops at the edge of the screen
+        b'A' => terminal.set_cursor(row.saturating_sub(params.get(0, 1)), col),
+        b'B' => terminal.set_cursor(::core::cmp::min(row + params.get(0, 1), height - 1), col),
+        b'C' => terminal.set_cursor(row, ::core::cmp::min(col + params.get(0, 1), width - 1)),
+        b'D' => terminal.set_cursor(row, col.saturating_sub(params.get(0, 1))),
+        // ED: 0 = to the end of the screen, 1 = to the cursor, 2 (and 3) = everything
+        b'J' => {
+            match params.get(0, 0) {
+                0 => {
+                    terminal.clear_columns(row, col, width);
+                    for r in row + 1..height {
+                        terminal.clear_columns(r, 0, width);
+                    }
+                }
+                1 => {
+                    for r in 0..row {
+                        terminal.clear_columns(r, 0, width);
+                    }
+                    terminal.clear_columns(row, 0, col + 1);
+                }
+                _ => {
+                    for r in 0..height {
+                        terminal.clear_columns(r, 0, width);
+                    }
+                }
+            }
+        }
+        // EL: 0 = to the end of the line, 1 = to the cursor, 2 = the whole line
+        b'K' => {
+            match params.get(0, 0) {
+                0 => terminal.clear_columns(row, col, width),
+                1 => terminal.clear_columns(row, 0, col + 1),
+                _ => terminal.clear_columns(row, 0, width),
+            }
+        }
+        b'm' => terminal.rendition().select_graphic_rendition(params),
+        b's' => {
+            let cursor = terminal.cursor();
+            *terminal.saved_cursor() = cursor;
+        }
+        b'u' => {
+            let (row, col) = *terminal.saved_cursor();
+            terminal.set_cursor(row, col);
+        }
+        // unsupported sequences are dropped
+        _ => {}
+    }
+}
+
+#[cfg(feature = "kernel-test")]
+mod tests {
+    use super::Terminal;
+    use vga_buffer::{Writer, CONSOLES};
+
+    // the last console isn't shown during the tests
+    fn with_console<F>(f: F) where F: FnOnce(&mut Writer) {
+        let mut writer = CONSOLES[3].lock();
+        writer.write_str("\x1b[0m\x1b[2J\x1b[H");
+        f(&mut writer);
+        writer.write_str("\x1b[0m\x1b[2J\x1b[H");
+    }
+
+    fn glyph(writer: &Writer, row: usize, col: usize) -> u8 {
+        writer.cell(row, col).0
+    }
+
+    fn foreground(writer: &Writer, row: usize, col: usize) -> u8 {
+        writer.cell(row, col).1 & 0x0f
+    }
+
+    kernel_test!(sgr_bold_and_bright_colors, {
+        with_console(|writer| {
+            // bold red, bold off, bright red, bold off keeps it bright
+            writer.write_str("\x1b[1;31mA\x1b[22mB\x1b[91mC\x1b[22mD\x1b[1m\x1b[22mE");
+            assert_eq!(foreground(writer, 0, 0), 12);
+            assert_eq!(foreground(writer, 0, 1), 4);
+            assert_eq!(foreground(writer, 0, 2), 12);
+            assert_eq!(foreground(writer, 0, 3), 12);
+            assert_eq!(foreground(writer, 0, 4), 12);
+
+            writer.write_str("\x1b[0mF");
+            assert_eq!(writer.cell(0, 5).1, writer.cell(1, 0).1);
+        });
+    });
+
+    kernel_test!(cursor_position_is_clamped, {
+        with_console(|writer| {
+            let (width, height) = writer.size();
+            writer.write_str("\x1b[999;999HX");
+            assert_eq!(glyph(writer, height - 1, width - 1), b'X');
+            writer.write_str("\x1b[HY");
+            assert_eq!(glyph(writer, 0, 0), b'Y');
+            writer.write_str("\x1b[5;5H\x1b[10AZ\x1b[10DW");
+            assert_eq!(glyph(writer, 0, 4), b'Z');
+            assert_eq!(glyph(writer, 0, 0), b'W');
+        });
+    });
+
+    kernel_test!(erase_in_line_and_display, {
+        with_console(|writer| {
+            writer.write_str("abcdef\x1b[1;3H\x1b[K");
+            assert_eq!(glyph(writer, 0, 1), b'b');
+            assert_eq!(glyph(writer, 0, 2), b' ');
+            assert_eq!(glyph(writer, 0, 5), b' ');
+
+            writer.write_str("\x1b[2;1Hxyz\x1b[2;2H\x1b[1J");
+            assert_eq!(glyph(writer, 0, 0), b' ');
+            assert_eq!(glyph(writer, 0, 1), b' ');
+            assert_eq!(glyph(writer, 1, 1), b' ');
+            assert_eq!(glyph(writer, 1, 2), b'z');
+
+            writer.write_str("\x1b[2J");
+            assert_eq!(glyph(writer, 1, 2), b' ');
+        });
+    });
+
+    kernel_test!(save_and_restore_cursor, {
+        with_console(|writer| {
+            writer.write_str("\x1b[3;4H\x1b[s\x1b[HA\x1b[uB");
+            assert_eq!(glyph(writer, 2, 3), b'B');
+            writer.write_str("\x1b[5;6H\x1b7\x1b[HC\x1b8D");
+            assert_eq!(glyph(writer, 4, 5), b'D');
+        });
+    });
+
+    // the parameters after the 8th are dropped, not merged into the 8th
+    kernel_test!(too_many_csi_parameters, {
+        with_console(|writer| {
+            writer.write_str("\x1b[0;0;0;0;0;0;0;3;1mX");
+            assert_eq!(writer.cell(0, 0).1, writer.cell(1, 0).1);
+            writer.write_str("\x1b[0;0;0;0;0;0;0;31;32;1mY");
+            assert_eq!(foreground(writer, 0, 1), 4);
+        });
+    });
+}
+
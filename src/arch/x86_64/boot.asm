@@ -2,6 +2,9 @@
 
 ;exports a label, start will be the entry point for the kernel
 global start
+; the bounds of the boot stack, for walking the frame pointer chain
+global stack_bottom
+global stack_top
 extern long_mode_start
 
 section .text   ;section for executable code
@@ -12,6 +12,9 @@ long_mode_start:
     mov fs, ax
     mov gs, ax
 
+    ; end of the frame pointer chain, GRUB leaves rbp undefined
+    xor rbp, rbp
+
     ; call the rust main
     extern rust_main        ; tell nasm that the function is defined in another file
     call rust_main
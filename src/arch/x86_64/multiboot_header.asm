@@ -13,6 +13,18 @@ header_start:
 
  ; insert optional multiboot tags here
 
+ ; framebuffer tag, asks for a graphics mode (optional, GRUB may stay in text mode)
+ align 8, db 0
+ dw 5    ; type
+ dw 1    ; flags, optional
+ dd 20   ; size
+ dd 1024 ; width
+ dd 768  ; height
+ dd 32   ; depth
+
+ ; tags are 8 byte aligned
+ align 8, db 0
+
  ; required end tag
  dw 0    ; type
  dw 0    ; flags
@@ -0,0 +1,519 @@
+// 2D drawing on a linear pixel buffer
+// a Canvas draws into any byte buffer with the layout of a framebuffer: rows of `pitch` bytes,
+// pixels in the framebuffer's pixel format; all coordinates may lie outside of the canvas,
+// everything is clipped to it
+// DoubleBuffer draws into a canvas in normal memory and copies only the changed part
+// (the dirty rectangle) to the framebuffer on flush, so half-drawn frames are never shown
+
+use core::{cmp, ptr};
+use alloc::vec::Vec;
+use framebuffer::PixelFormat;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb { red: red, green: green, blue: blue }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    /// The smallest rectangle that contains both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x = cmp::min(self.x, other.x);
+        let y = cmp::min(self.y, other.y);
+        let right = cmp::max(self.x + self.width, other.x + other.width);
+        let bottom = cmp::max(self.y + self.height, other.y + other.height);
+        Rect { x: x, y: y, width: right - x, height: bottom - y }
+    }
+}
+
+/// A bitmap for `Canvas::blit`, the pixels are stored row by row.
+pub struct Bitmap<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: &'a [Rgb],
+}
+
+pub struct Canvas<B> {
+    buffer: B,
+    width: usize,
+    height: usize,
+    // bytes per row
+    pitch: usize,
+    format: PixelFormat,
+    // the part that was drawn to since the last take_dirty
+    dirty: Option<Rect>,
+}
+
+impl<B> Canvas<B> where B: AsRef<[u8]> + AsMut<[u8]> {
+    pub fn new(buffer: B, width: usize, height: usize, pitch: usize, format: PixelFormat)
+        -> Canvas<B>
+    {
+        assert!(pitch >= width * format.bytes_per_pixel, "pitch is smaller than a row");
+        assert!(buffer.as_ref().len() >= pitch * height, "buffer is too small");
+        Canvas {
+            buffer: buffer,
+            width: width,
+            height: height,
+            pitch: pitch,
+            format: format,
+            dirty: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        self.buffer.as_ref()
+    }
+
+    /// Returns the area that was drawn to since the last call and resets it.
+    pub fn take_dirty(&mut self) -> Option<Rect> {
+        self.dirty.take()
+    }
+
+    /// The raw value of a pixel in the canvas' pixel format.
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        let offset = y * self.pitch + x * self.format.bytes_per_pixel;
+        let bytes = &self.buffer.as_ref()[offset..offset + self.format.bytes_per_pixel];
+        bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)
+    }
+
+    // x and y must be inside of the canvas
+    fn put_raw(&mut self, x: usize, y: usize, value: u32) {
+        let bytes_per_pixel = self.format.bytes_per_pixel;
+        let offset = y * self.pitch + x * bytes_per_pixel;
+        let bytes = &mut self.buffer.as_mut()[offset..offset + bytes_per_pixel];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (value >> (8 * i)) as u8;
+        }
+    }
+
+    // clips the rectangle from (x0, y0) to (x1, y1) (exclusive) to the canvas
+    fn clip(&self, x0: isize, y0: isize, x1: isize, y1: isize) -> Option<Rect> {
+        let x0 = cmp::max(x0, 0) as usize;
+        let y0 = cmp::max(y0, 0) as usize;
+        let x1 = cmp::min(cmp::max(x1, 0) as usize, self.width);
+        let y1 = cmp::min(cmp::max(y1, 0) as usize, self.height);
+        if x0 < x1 && y0 < y1 {
+            Some(Rect { x: x0, y: y0, width: x1 - x0, height: y1 - y0 })
+        } else {
+            None
+        }
+    }
+
+    fn mark_dirty(&mut self, x0: isize, y0: isize, x1: isize, y1: isize) {
+        if let Some(rect) = self.clip(x0, y0, x1, y1) {
+            self.dirty = Some(match self.dirty {
+                Some(dirty) => dirty.union(&rect),
+                None => rect,
+            });
+        }
+    }
+
+    // plots a pixel without marking it dirty, the callers mark their bounding box
+    fn plot(&mut self, x: isize, y: isize, value: u32) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.put_raw(x as usize, y as usize, value);
+        }
+    }
+
+    // fills a clipped rectangle with a raw pixel value
+    fn fill(&mut self, rect: Rect, value: u32) {
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                self.put_raw(x, y, value);
+            }
+        }
+    }
+
+    pub fn put_pixel(&mut self, x: isize, y: isize, color: Rgb) {
+        let value = self.format.encode(color.red, color.green, color.blue);
+        self.plot(x, y, value);
+        self.mark_dirty(x, y, x + 1, y + 1);
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        let (width, height) = (self.width as isize, self.height as isize);
+        self.fill_rect(0, 0, width, height, color);
+    }
+
+    pub fn fill_rect(&mut self, x: isize, y: isize, width: isize, height: isize, color: Rgb) {
+        let value = self.format.encode(color.red, color.green, color.blue);
+        if let Some(rect) = self.clip(x, y, x + width, y + height) {
+            self.fill(rect, value);
+            self.mark_dirty(x, y, x + width, y + height);
+        }
+    }
+
+    /// Draws the outline of a rectangle, one pixel wide.
+    pub fn rect(&mut self, x: isize, y: isize, width: isize, height: isize, color: Rgb) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height - 1, width, 1, color);
+        self.fill_rect(x, y + 1, 1, height - 2, color);
+        self.fill_rect(x + width - 1, y + 1, 1, height - 2, color);
+    }
+
+    /// Draws a line from (x0, y0) to (x1, y1), both ends included, with
+    /// Bresenham's algorithm.
+    pub fn line(&mut self, x0: isize, y0: isize, x1: isize, y1: isize, color: Rgb) {
+        let value = self.format.encode(color.red, color.green, color.blue);
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        // the error term is dx + dy scaled by the distance to the ideal line
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.plot(x, y, value);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let error2 = 2 * error;
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+        self.mark_dirty(cmp::min(x0, x1), cmp::min(y0, y1),
+                        cmp::max(x0, x1) + 1, cmp::max(y0, y1) + 1);
+    }
+
+    // walks one octant of a circle with the midpoint algorithm, calls `f` with
+    // (x, y) where x >= y, the other octants are mirrored
+    fn circle_octant<F>(radius: isize, mut f: F) where F: FnMut(isize, isize) {
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+        while x >= y {
+            f(x, y);
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Draws the outline of a circle around (cx, cy).
+    pub fn circle(&mut self, cx: isize, cy: isize, radius: isize, color: Rgb) {
+        if radius < 0 {
+            return;
+        }
+        let value = self.format.encode(color.red, color.green, color.blue);
+        Self::circle_octant(radius, |x, y| {
+            for &(px, py) in &[(x, y), (y, x), (-y, x), (-x, y),
+                               (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.plot(cx + px, cy + py, value);
+            }
+        });
+        self.mark_dirty(cx - radius, cy - radius, cx + radius + 1, cy + radius + 1);
+    }
+
+    /// Draws a filled circle around (cx, cy).
+    pub fn fill_circle(&mut self, cx: isize, cy: isize, radius: isize, color: Rgb) {
+        if radius < 0 {
+            return;
+        }
+        // spans between the mirrored points of the outline
+        Self::circle_octant(radius, |x, y| {
+            self.fill_rect(cx - x, cy + y, 2 * x + 1, 1, color);
+            self.fill_rect(cx - x, cy - y, 2 * x + 1, 1, color);
+            self.fill_rect(cx - y, cy + x, 2 * y + 1, 1, color);
+            self.fill_rect(cx - y, cy - x, 2 * y + 1, 1, color);
+        });
+    }
+
+    /// Copies a bitmap to (x, y), the parts outside of the canvas are cut off.
+    pub fn blit(&mut self, x: isize, y: isize, bitmap: &Bitmap) {
+        assert!(bitmap.pixels.len() >= bitmap.width * bitmap.height, "bitmap is too small");
+        let (width, height) = (bitmap.width as isize, bitmap.height as isize);
+        let rect = match self.clip(x, y, x + width, y + height) {
+            Some(rect) => rect,
+            None => return,
+        };
+        for row in rect.y..rect.y + rect.height {
+            let source_row = (row as isize - y) as usize;
+            for column in rect.x..rect.x + rect.width {
+                let source_column = (column as isize - x) as usize;
+                let color = bitmap.pixels[source_row * bitmap.width + source_column];
+                let value = self.format.encode(color.red, color.green, color.blue);
+                self.put_raw(column, row, value);
+            }
+        }
+        self.mark_dirty(x, y, x + width, y + height);
+    }
+}
+
+/// A canvas in normal memory whose changes are copied to the framebuffer
+/// by `flush`.
+pub struct DoubleBuffer {
+    back: Canvas<Vec<u8>>,
+    front: *mut u8,
+    front_pitch: usize,
+}
+
+// the front buffer is only written through &mut self
+unsafe impl Send for DoubleBuffer {}
+
+impl DoubleBuffer {
+    /// Creates a double buffer for the framebuffer at `front`, which must
+    /// be valid for `front_pitch * height` bytes as long as the double
+    /// buffer lives. The back buffer starts as a copy of it.
+    pub unsafe fn new(front: *mut u8, width: usize, height: usize, front_pitch: usize,
+                      format: PixelFormat) -> DoubleBuffer
+    {
+        let pitch = width * format.bytes_per_pixel;
+        let mut back = vec![0; pitch * height];
+        for y in 0..height {
+            ptr::copy_nonoverlapping(front.offset((y * front_pitch) as isize),
+                                     back[y * pitch..].as_mut_ptr(), pitch);
+        }
+        DoubleBuffer {
+            back: Canvas::new(back, width, height, pitch, format),
+            front: front,
+            front_pitch: front_pitch,
+        }
+    }
+
+    /// The canvas to draw on, nothing is shown before `flush`.
+    pub fn canvas(&mut self) -> &mut Canvas<Vec<u8>> {
+        &mut self.back
+    }
+
+    /// Copies the rectangle that changed since the last flush to the
+    /// framebuffer and returns it.
+    pub fn flush(&mut self) -> Option<Rect> {
+        let rect = self.back.take_dirty();
+        if let Some(rect) = rect {
+            let bytes_per_pixel = self.back.format.bytes_per_pixel;
+            let pitch = self.back.pitch();
+            let len = rect.width * bytes_per_pixel;
+            for y in rect.y..rect.y + rect.height {
+                let x_offset = rect.x * bytes_per_pixel;
+                let source = &self.back.buffer()[y * pitch + x_offset..][..len];
+                unsafe {
+                    ptr::copy_nonoverlapping(source.as_ptr(),
+                        self.front.offset((y * self.front_pitch + x_offset) as isize), len);
+                }
+            }
+        }
+        rect
+    }
+}
+
+// host tests (cargo test), the canvases draw into a Vec
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use framebuffer::{Channel, PixelFormat};
+    use std::vec::Vec;
+
+    const WHITE: Rgb = Rgb::new(0xff, 0xff, 0xff);
+    const RED: Rgb = Rgb::new(0xff, 0, 0);
+
+    // the usual 32 bit format of QEMU's standard VGA, blue in the lowest byte
+    fn xrgb32() -> PixelFormat {
+        PixelFormat {
+            bytes_per_pixel: 4,
+            red: Channel { position: 16, size: 8 },
+            green: Channel { position: 8, size: 8 },
+            blue: Channel { position: 0, size: 8 },
+        }
+    }
+
+    fn bgr24() -> PixelFormat {
+        PixelFormat {
+            bytes_per_pixel: 3,
+            red: Channel { position: 0, size: 8 },
+            green: Channel { position: 8, size: 8 },
+            blue: Channel { position: 16, size: 8 },
+        }
+    }
+
+    fn rgb565() -> PixelFormat {
+        PixelFormat {
+            bytes_per_pixel: 2,
+            red: Channel { position: 11, size: 5 },
+            green: Channel { position: 5, size: 6 },
+            blue: Channel { position: 0, size: 5 },
+        }
+    }
+
+    fn new_canvas(width: usize, height: usize, format: PixelFormat) -> Canvas<Vec<u8>> {
+        let pitch = width * format.bytes_per_pixel;
+        Canvas::new(vec![0; pitch * height], width, height, pitch, format)
+    }
+
+    // FNV-1a over the pixel bytes
+    fn checksum(bytes: &[u8]) -> u32 {
+        bytes.iter().fold(0x811c9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x01000193))
+    }
+
+    // number of pixels that aren't black
+    fn set_pixels<B: AsRef<[u8]> + AsMut<[u8]>>(canvas: &Canvas<B>) -> usize {
+        let mut count = 0;
+        for y in 0..canvas.height() {
+            for x in 0..canvas.width() {
+                if canvas.pixel(x, y) != 0 {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let color = Rgb::new(0x12, 0x34, 0x56);
+        let mut canvas = new_canvas(2, 1, xrgb32());
+        canvas.put_pixel(1, 0, color);
+        assert_eq!(canvas.pixel(1, 0), 0x123456);
+        assert_eq!(canvas.buffer(), &[0, 0, 0, 0, 0x56, 0x34, 0x12, 0]);
+
+        let mut canvas = new_canvas(1, 1, bgr24());
+        canvas.put_pixel(0, 0, color);
+        assert_eq!(canvas.buffer(), &[0x12, 0x34, 0x56]);
+
+        let mut canvas = new_canvas(1, 1, rgb565());
+        canvas.put_pixel(0, 0, WHITE);
+        assert_eq!(canvas.pixel(0, 0), 0xffff);
+    }
+
+    // 30 bit mode with 10 bit channels
+    #[test]
+    fn wide_channels() {
+        let format = PixelFormat {
+            bytes_per_pixel: 4,
+            red: Channel { position: 20, size: 10 },
+            green: Channel { position: 10, size: 10 },
+            blue: Channel { position: 0, size: 10 },
+        };
+        assert_eq!(format.encode(0xff, 0, 0), 0x3fc0_0000);
+        assert_eq!(format.encode(0xff, 0xff, 0xff), 0x3fcf_f3fc);
+    }
+
+    #[test]
+    fn lines() {
+        let mut canvas = new_canvas(16, 16, xrgb32());
+        canvas.line(0, 0, 15, 15, WHITE);
+        canvas.line(15, 0, 0, 5, WHITE);
+        canvas.line(3, 12, 3, 2, WHITE);
+        // both ends are drawn
+        assert_eq!(canvas.pixel(15, 15), 0xffffff);
+        assert_eq!(canvas.pixel(0, 5), 0xffffff);
+        assert_eq!(canvas.pixel(3, 12), 0xffffff);
+        assert_eq!(set_pixels(&canvas), 40);
+        assert_eq!(checksum(canvas.buffer()), 0xde60f8f5);
+    }
+
+    #[test]
+    fn rectangles() {
+        let mut canvas = new_canvas(16, 16, xrgb32());
+        canvas.fill_rect(2, 2, 4, 3, RED);
+        canvas.rect(8, 8, 5, 4, WHITE);
+        assert_eq!(set_pixels(&canvas), 4 * 3 + 2 * 5 + 2 * 2);
+        assert_eq!(canvas.pixel(10, 9), 0);
+        assert_eq!(checksum(canvas.buffer()), 0xb42e94a5);
+
+        // clipped at all sides
+        canvas.fill_rect(-4, -4, 100, 100, RED);
+        assert_eq!(set_pixels(&canvas), 16 * 16);
+    }
+
+    #[test]
+    fn circles() {
+        let mut canvas = new_canvas(32, 32, bgr24());
+        canvas.circle(10, 10, 6, WHITE);
+        assert_eq!(canvas.pixel(16, 10), 0xffffff);
+        assert_eq!(canvas.pixel(10, 4), 0xffffff);
+        assert_eq!(canvas.pixel(10, 10), 0);
+        assert_eq!(checksum(canvas.buffer()), 0x2efa42d5);
+
+        let mut filled = new_canvas(32, 32, bgr24());
+        filled.fill_circle(10, 10, 6, WHITE);
+        // the filled circle covers the outline
+        for y in 0..32 {
+            for x in 0..32 {
+                if canvas.pixel(x, y) != 0 {
+                    assert_eq!(filled.pixel(x, y), 0xffffff);
+                }
+            }
+        }
+        assert_eq!(checksum(filled.buffer()), 0x564f7200);
+
+        // partly outside of the canvas
+        filled.fill_circle(30, 30, 5, RED);
+        assert_eq!(filled.pixel(31, 31), 0x0000ff);
+    }
+
+    #[test]
+    fn blit_clips() {
+        let pixels: Vec<Rgb> = (0..12).map(|i| Rgb::new(i, 0, 0)).collect();
+        let bitmap = Bitmap { width: 4, height: 3, pixels: &pixels };
+        let mut canvas = new_canvas(4, 4, xrgb32());
+        canvas.blit(-1, 2, &bitmap);
+
+        // only the rows 0 and 1 and the columns 1 to 3 are visible
+        assert_eq!(canvas.pixel(0, 2), 0x010000);
+        assert_eq!(canvas.pixel(2, 3), 0x070000);
+        assert_eq!(canvas.pixel(3, 3), 0);
+        assert_eq!(canvas.take_dirty(), Some(Rect { x: 0, y: 2, width: 3, height: 2 }));
+        assert_eq!(checksum(canvas.buffer()), 0x35bc1aa1);
+    }
+
+    #[test]
+    fn double_buffer_flushes_dirty_rect() {
+        let format = xrgb32();
+        // the front buffer has 4 bytes of padding per row
+        let front_pitch = 8 * 4 + 4;
+        let mut front = vec![0u8; front_pitch * 8];
+        let mut buffer = unsafe { DoubleBuffer::new(front.as_mut_ptr(), 8, 8, front_pitch, format) };
+
+        buffer.canvas().fill_rect(1, 1, 2, 2, WHITE);
+        buffer.canvas().put_pixel(5, 6, RED);
+        // nothing is shown before the flush
+        assert!(front.iter().all(|&b| b == 0));
+
+        assert_eq!(buffer.flush(), Some(Rect { x: 1, y: 1, width: 5, height: 6 }));
+        let front_canvas = Canvas::new(&mut front[..], 8, 8, front_pitch, format);
+        assert_eq!(front_canvas.pixel(2, 2), 0xffffff);
+        assert_eq!(front_canvas.pixel(5, 6), 0xff0000);
+        assert_eq!(set_pixels(&front_canvas), 5);
+        assert_eq!(buffer.flush(), None);
+    }
+}
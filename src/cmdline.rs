@@ -0,0 +1,60 @@
+// the kernel command line that GRUB passes behind the kernel path in grub.cfg
+// it is copied out of the multiboot information at boot, options are separated by spaces
+// and have the form `key=value` or just `key`
+
+use core::str;
+use spin::Once;
+use multiboot_tags::{self, TagHeader};
+
+const MAX_LENGTH: usize = 256;
+
+struct CommandLine {
+    bytes: [u8; MAX_LENGTH],
+    len: usize,
+}
+
+static COMMAND_LINE: Once<CommandLine> = Once::new();
+
+/// Copies the command line out of the multiboot information structure.
+/// Longer command lines are cut off after 256 bytes.
+pub fn init(multiboot_information_address: usize) {
+    COMMAND_LINE.call_once(|| {
+        let mut cmdline = CommandLine { bytes: [0; MAX_LENGTH], len: 0 };
+        let tag = unsafe {
+            multiboot_tags::find_tag(multiboot_information_address, multiboot_tags::COMMAND_LINE)
+        };
+        if let Some(tag) = tag {
+            // the tag header is followed by a null terminated string
+            let size = unsafe { (*(tag as *const TagHeader)).size } as usize - 8;
+            let string = (tag + 8) as *const u8;
+            for i in 0..::core::cmp::min(size, MAX_LENGTH) {
+                let byte = unsafe { *string.offset(i as isize) };
+                if byte == 0 {
+                    break;
+                }
+                cmdline.bytes[i] = byte;
+                cmdline.len += 1;
+            }
+        }
+        cmdline
+    });
+}
+
+/// The whole command line, empty before `init`.
+pub fn command_line() -> &'static str {
+    COMMAND_LINE.try()
+        .and_then(|cmdline| str::from_utf8(&cmdline.bytes[..cmdline.len]).ok())
+        .unwrap_or("")
+}
+
+/// Returns the value of the option `key`, or an empty string if the option
+/// is given without a value.
+pub fn option(key: &str) -> Option<&'static str> {
+    for arg in command_line().split_whitespace() {
+        let mut parts = arg.splitn(2, '=');
+        if parts.next() == Some(key) {
+            return Some(parts.next().unwrap_or(""));
+        }
+    }
+    None
+}
@@ -0,0 +1,207 @@
+// output sinks for print!/println!
+// every message is written to all enabled consoles of the registry: the VGA text buffer,
+// the serial port and an in-memory ring buffer that tests can read the output back from
+// more consoles can be registered at runtime, the registry is a fixed array so that
+// printing works before the heap is ready
+
+use core::fmt;
+use spin::Mutex;
+use log::Level;
+
+const MAX_CONSOLES: usize = 8;
+const RING_BUFFER_SIZE: usize = 4096;
+
+/// An output device for the kernel messages.
+pub trait Console: Sync {
+    fn write_str(&self, s: &str);
+
+    /// Writes (a part of) a log message. Consoles that can show colors
+    /// override this.
+    fn write_log(&self, _level: Level, s: &str) {
+        self.write_str(s)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sink {
+    name: &'static str,
+    console: &'static Console,
+    enabled: bool,
+}
+
+// writes to one of the virtual consoles of the VGA text mode
+pub struct VgaConsole(pub usize);
+
+impl Console for VgaConsole {
+    fn write_str(&self, s: &str) {
+        ::vga_buffer::CONSOLES[self.0].lock().write_str(s);
+    }
+
+    fn write_log(&self, level: Level, s: &str) {
+        ::vga_buffer::CONSOLES[self.0].lock().write_str_colored(s, level.color());
+    }
+}
+
+pub struct SerialConsole;
+
+impl Console for SerialConsole {
+    fn write_str(&self, s: &str) {
+        use core::fmt::Write;
+        ::serial::COM1.lock().write_str(s).unwrap();
+    }
+}
+
+// keeps the last RING_BUFFER_SIZE bytes, older output is overwritten
+pub struct RingBuffer {
+    data: [u8; RING_BUFFER_SIZE],
+    // index of the oldest byte
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    pub const fn new() -> RingBuffer {
+        RingBuffer {
+            data: [0; RING_BUFFER_SIZE],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        let end = (self.start + self.len) % RING_BUFFER_SIZE;
+        self.data[end] = byte;
+        if self.len < RING_BUFFER_SIZE {
+            self.len += 1;
+        } else {
+            self.start = (self.start + 1) % RING_BUFFER_SIZE;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Copies the buffered bytes, oldest first, into `buf` and returns their
+    /// number. If `buf` is too small, only the newest bytes are copied.
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let count = ::core::cmp::min(buf.len(), self.len);
+        let skip = self.len - count;
+        for i in 0..count {
+            buf[i] = self.data[(self.start + skip + i) % RING_BUFFER_SIZE];
+        }
+        count
+    }
+
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
+
+pub struct RingBufferConsole {
+    pub buffer: Mutex<RingBuffer>,
+}
+
+impl Console for RingBufferConsole {
+    fn write_str(&self, s: &str) {
+        let mut buffer = self.buffer.lock();
+        for byte in s.bytes() {
+            buffer.push(byte);
+        }
+    }
+}
+
+// the kernel output goes to the first virtual console
+pub static VGA_CONSOLE: VgaConsole = VgaConsole(0);
+pub static SERIAL_CONSOLE: SerialConsole = SerialConsole;
+pub static RING_BUFFER_CONSOLE: RingBufferConsole = RingBufferConsole {
+    buffer: Mutex::new(RingBuffer::new()),
+};
+
+static CONSOLES: Mutex<[Option<Sink>; MAX_CONSOLES]> = Mutex::new([
+    Some(Sink { name: "vga", console: &VGA_CONSOLE, enabled: true }),
+    // the serial port only mirrors the output if the serial-mirror feature is set
+    Some(Sink { name: "serial", console: &SERIAL_CONSOLE, enabled: cfg!(feature = "serial-mirror") }),
+    Some(Sink { name: "ring", console: &RING_BUFFER_CONSOLE, enabled: true }),
+    None, None, None, None, None,
+]);
+
+/// Adds a console under the given name. It receives all output printed
+/// from now on if `enabled` is true.
+pub fn register(name: &'static str, console: &'static Console, enabled: bool) {
+    let mut consoles = CONSOLES.lock();
+    assert!(consoles.iter().filter_map(|c| *c).all(|sink| sink.name != name),
+            "console {} is already registered", name);
+    let slot = consoles.iter_mut().find(|slot| slot.is_none())
+        .expect("too many consoles");
+    *slot = Some(Sink { name: name, console: console, enabled: enabled });
+}
+
+/// Enables or disables the console with the given name. Returns false if
+/// there is no such console.
+pub fn set_enabled(name: &str, enabled: bool) -> bool {
+    let mut consoles = CONSOLES.lock();
+    match consoles.iter_mut().filter_map(|c| c.as_mut()).find(|sink| sink.name == name) {
+        Some(sink) => {
+            sink.enabled = enabled;
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn is_enabled(name: &str) -> bool {
+    CONSOLES.lock().iter().filter_map(|c| *c)
+        .any(|sink| sink.name == name && sink.enabled)
+}
+
+// lets write_fmt format directly into a console
+struct Adapter<'a> {
+    console: &'a Console,
+    level: Option<Level>,
+}
+
+impl<'a> fmt::Write for Adapter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.level {
+            Some(level) => self.console.write_log(level, s),
+            None => self.console.write_str(s),
+        }
+        Ok(())
+    }
+}
+
+fn write_all(level: Option<Level>, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    // copy the registry so that a console can print (or panic) without deadlocking
+    let consoles = *CONSOLES.lock();
+    for sink in consoles.iter().filter_map(|c| *c).filter(|sink| sink.enabled) {
+        Adapter { console: sink.console, level: level }.write_fmt(args).unwrap();
+    }
+}
+
+/// Writes the message to every enabled console.
+pub fn write_fmt(args: fmt::Arguments) {
+    write_all(None, args)
+}
+
+/// Writes a log message to every enabled console.
+pub fn write_log(level: Level, args: fmt::Arguments) {
+    write_all(Some(level), args)
+}
+
+#[cfg(feature = "kernel-test")]
+mod tests {
+    use super::RING_BUFFER_CONSOLE;
+
+    kernel_test!(ring_buffer_captures_print, {
+        RING_BUFFER_CONSOLE.buffer.lock().clear();
+        println!("captured {}", 42);
+
+        let mut buf = [0; 32];
+        let len = RING_BUFFER_CONSOLE.buffer.lock().read(&mut buf);
+        assert_eq!(&buf[..len], b"captured 42\n");
+    });
+}
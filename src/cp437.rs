@@ -0,0 +1,47 @@
+// translation of unicode characters to code page 437, the character set of the VGA text mode
+// ASCII is the same in both, the other glyphs are looked up in the tables below
+
+// replacement for characters without a glyph (■)
+pub const UNKNOWN: u8 = 0xfe;
+
+// glyphs 0x01 to 0x1f, the VGA shows them instead of control characters
+const LOW: [char; 31] = [
+         '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+// glyphs 0x80 to 0xff
+const HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+// characters that look like one of the glyphs above
+// (greek beta, greek mu, n-ary sum, ohm sign, element of, house)
+const SIMILAR: [(char, u8); 6] = [
+    ('\u{3b2}', 0xe1), ('\u{3bc}', 0xe6), ('\u{2211}', 0xe4),
+    ('\u{2126}', 0xea), ('\u{2208}', 0xee), ('\u{2302}', 0x7f),
+];
+
+/// Returns the code page 437 glyph of the character, `UNKNOWN` if there is
+/// none. Doesn't translate ASCII, the control characters keep their meaning.
+pub fn glyph(c: char) -> u8 {
+    if (c as u32) < 0x80 {
+        return c as u8;
+    }
+    if let Some(index) = HIGH.iter().position(|&g| g == c) {
+        return 0x80 + index as u8;
+    }
+    if let Some(index) = LOW.iter().position(|&g| g == c) {
+        return 0x01 + index as u8;
+    }
+    SIMILAR.iter().find(|&&(similar, _)| similar == c)
+        .map(|&(_, glyph)| glyph)
+        .unwrap_or(UNKNOWN)
+}
@@ -0,0 +1,150 @@
+// kernel log buffer (dmesg)
+// keeps the last MAX_ENTRIES log messages with their sequence number and level, so that
+// boot messages can be read after they have scrolled off the screen
+// writers only try to take the lock and drop the message if it is held, so storing is safe
+// from any context, including the panic handler; dropped messages show up as a gap in the
+// sequence numbers
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use log::Level;
+
+const MAX_ENTRIES: usize = 256;
+// longer messages are cut off
+const MAX_TEXT_LENGTH: usize = 120;
+
+#[derive(Clone, Copy)]
+pub struct Entry {
+    pub sequence: usize,
+    pub level: Level,
+    text: [u8; MAX_TEXT_LENGTH],
+    len: usize,
+}
+
+impl Entry {
+    /// The module path and the message.
+    pub fn text(&self) -> &str {
+        // only whole characters are copied in
+        unsafe { ::core::str::from_utf8_unchecked(&self.text[..self.len]) }
+    }
+}
+
+impl fmt::Write for Entry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = ::core::cmp::min(s.len(), MAX_TEXT_LENGTH - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.text[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+struct LogBuffer {
+    entries: [Entry; MAX_ENTRIES],
+    // index of the next entry to write
+    next: usize,
+    count: usize,
+    // sequence number of the first entry that wasn't drained to serial yet
+    undrained: usize,
+}
+
+impl LogBuffer {
+    // the i-th stored entry, oldest first
+    fn entry(&self, i: usize) -> &Entry {
+        &self.entries[(self.next + MAX_ENTRIES - self.count + i) % MAX_ENTRIES]
+    }
+}
+
+static LOG: Mutex<LogBuffer> = Mutex::new(LogBuffer {
+    entries: [Entry { sequence: 0, level: Level::Info, text: [0; MAX_TEXT_LENGTH], len: 0 }; MAX_ENTRIES],
+    next: 0,
+    count: 0,
+    undrained: 0,
+});
+
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Stores a log message. Drops it if the buffer is locked, e.g. when the
+/// panic handler interrupted a reader.
+pub fn store(level: Level, module_path: &str, args: fmt::Arguments) {
+    use core::fmt::Write;
+
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+    let mut log = match LOG.try_lock() {
+        Some(log) => log,
+        None => return,
+    };
+
+    let index = log.next;
+    {
+        let entry = &mut log.entries[index];
+        entry.sequence = sequence;
+        entry.level = level;
+        entry.len = 0;
+        let _ = write!(entry, "{}: {}", module_path, args);
+    }
+    log.next = (index + 1) % MAX_ENTRIES;
+    if log.count < MAX_ENTRIES {
+        log.count += 1;
+    }
+}
+
+/// Calls `f` for every stored message, oldest first. Messages that are
+/// logged from inside `f` are dropped.
+pub fn read<F>(mut f: F) where F: FnMut(&Entry) {
+    let log = LOG.lock();
+    for i in 0..log.count {
+        f(log.entry(i));
+    }
+}
+
+/// Prints all stored messages.
+pub fn dmesg() {
+    read(|entry| println!("[{:5}] {:5} {}", entry.sequence, entry.level, entry.text()));
+}
+
+/// Writes the messages that weren't drained before to the serial port.
+pub fn drain_to_serial() {
+    let mut log = LOG.lock();
+    for i in 0..log.count {
+        let entry = *log.entry(i);
+        if entry.sequence >= log.undrained {
+            serial_println!("[{:5}] {:5} {}", entry.sequence, entry.level, entry.text());
+        }
+    }
+    log.undrained = SEQUENCE.load(Ordering::Relaxed);
+}
+
+#[cfg(feature = "kernel-test")]
+mod tests {
+    use log::Level;
+
+    kernel_test!(dmesg_keeps_messages, {
+        warn!("dmesg test {}", 42);
+
+        let mut last = None;
+        super::read(|entry| last = Some(*entry));
+        let last = last.unwrap();
+        assert_eq!(last.level, Level::Warn);
+        assert_eq!(last.text(), "blog_os::dmesg::tests: dmesg test 42");
+    });
+
+    // a module whose messages the consoles drop
+    mod filtered {
+        use log::{self, Level};
+
+        kernel_test!(dmesg_keeps_filtered_messages, {
+            log::set_module_level("dmesg::tests::filtered", None).unwrap();
+            warn!("filtered {}", 43);
+
+            let mut last = None;
+            ::dmesg::read(|entry| last = Some(*entry));
+            let last = last.unwrap();
+            assert_eq!(last.level, Level::Warn);
+            assert_eq!(last.text(), "blog_os::dmesg::tests::filtered: filtered 43");
+        });
+    }
+}
@@ -0,0 +1,427 @@
+// framebuffer console for graphics modes
+// multiboot_header.asm asks GRUB for a 1024x768x32 mode, GRUB describes the mode it set in the
+// framebuffer tag of the boot information; if it stays in text mode, the VGA console is used
+// text is drawn with the PSF font in fonts/, the glyphs are in code page 437 order
+// escape sequences (colors, cursor movement) are handled by ansi.rs, like on the VGA console
+
+use core::{ptr, str};
+use alloc::Vec;
+use spin::{Mutex, MutexGuard};
+use console::{self, Console};
+use log::Level;
+use multiboot_tags::{self, TagHeader};
+use psf::Font;
+use canvas::DoubleBuffer;
+use ansi::{self, Action, EscapeParser, Rendition, Terminal};
+use vga_buffer::Color;
+
+static FONT_DATA: &'static [u8] = include_bytes!("fonts/default-8x16.psf");
+
+const TAB_WIDTH: usize = 8;
+
+// RGB values of the 16 VGA text colors
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xaa), (0x00, 0xaa, 0x00), (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00), (0xaa, 0x00, 0xaa), (0xaa, 0x55, 0x00), (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xff), (0x55, 0xff, 0x55), (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55), (0xff, 0x55, 0xff), (0xff, 0xff, 0x55), (0xff, 0xff, 0xff),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FramebufferType {
+    Indexed,
+    Rgb,
+    // EGA text mode, the VGA text buffer
+    Text,
+}
+
+/// Where a color channel lies in a pixel.
+#[derive(Debug, Clone, Copy)]
+pub struct Channel {
+    pub position: u8,
+    pub size: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PixelFormat {
+    pub bytes_per_pixel: usize,
+    pub red: Channel,
+    pub green: Channel,
+    pub blue: Channel,
+}
+
+impl PixelFormat {
+    /// Packs an 8 bit per channel color into a pixel value.
+    pub fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        fn channel(value: u8, channel: Channel) -> u32 {
+            // e.g. 10 bit channels of 30 bit modes
+            let value = if channel.size > 8 {
+                (value as u32) << (channel.size - 8)
+            } else {
+                (value as u32) >> (8 - channel.size)
+            };
+            value << channel.position
+        }
+        channel(red, self.red) | channel(green, self.green) | channel(blue, self.blue)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    pub address: usize,
+    pub pitch: usize,
+    pub width: usize,
+    pub height: usize,
+    pub bpp: u8,
+    pub framebuffer_type: FramebufferType,
+    // only valid for Rgb
+    pub format: PixelFormat,
+}
+
+// the framebuffer tag (type 8) as GRUB writes it, the color info follows the common part
+#[repr(C, packed)]
+struct FramebufferTag {
+    header: TagHeader,
+    address: u64,
+    pitch: u32,
+    width: u32,
+    height: u32,
+    bpp: u8,
+    framebuffer_type: u8,
+    reserved: u16,
+    red_position: u8,
+    red_size: u8,
+    green_position: u8,
+    green_size: u8,
+    blue_position: u8,
+    blue_size: u8,
+}
+
+/// Reads the framebuffer tag of the boot information.
+pub fn framebuffer_info(multiboot_information_address: usize) -> Option<FramebufferInfo> {
+    let tag = unsafe {
+        multiboot_tags::find_tag(multiboot_information_address, multiboot_tags::FRAMEBUFFER)
+    };
+    tag.map(|tag| {
+        let tag = unsafe { &*(tag as *const FramebufferTag) };
+        FramebufferInfo {
+            address: tag.address as usize,
+            pitch: tag.pitch as usize,
+            width: tag.width as usize,
+            height: tag.height as usize,
+            bpp: tag.bpp,
+            framebuffer_type: match tag.framebuffer_type {
+                0 => FramebufferType::Indexed,
+                1 => FramebufferType::Rgb,
+                _ => FramebufferType::Text,
+            },
+            format: PixelFormat {
+                bytes_per_pixel: (tag.bpp as usize + 7) / 8,
+                red: Channel { position: tag.red_position, size: tag.red_size },
+                green: Channel { position: tag.green_position, size: tag.green_size },
+                blue: Channel { position: tag.blue_position, size: tag.blue_size },
+            },
+        }
+    })
+}
+
+// a character on the screen, the text is kept in RAM so that scrolling doesn't read the
+// write-combining framebuffer (every read is uncached); the colors are VGA color numbers
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    glyph: u8,
+    foreground: u8,
+    background: u8,
+}
+
+pub struct FramebufferWriter {
+    // virtual address of the mapped framebuffer
+    buffer: usize,
+    info: FramebufferInfo,
+    font: Font<'static>,
+    columns: usize,
+    rows: usize,
+    column_position: usize,
+    row_position: usize,
+    // the colors, changed by SGR escape sequences like on the VGA console
+    rendition: Rendition,
+    // position stored by ESC[s or ESC7 (row, column)
+    saved_position: (usize, usize),
+    // state of the escape sequence currently being written
+    parser: EscapeParser,
+    // the text shown on the screen, row by row
+    cells: Vec<Cell>,
+}
+
+impl FramebufferWriter {
+    fn new(buffer: usize, info: FramebufferInfo, font: Font<'static>) -> FramebufferWriter {
+        let (columns, rows) = (info.width / font.width, info.height / font.height);
+        let rendition = Rendition::new(Color::LightGray, Color::Black);
+        FramebufferWriter {
+            buffer: buffer,
+            info: info,
+            font: font,
+            columns: columns,
+            rows: rows,
+            column_position: 0,
+            row_position: 0,
+            rendition: rendition,
+            saved_position: (0, 0),
+            parser: EscapeParser::new(),
+            cells: vec![Cell { glyph: b' ', foreground: rendition.foreground,
+                               background: rendition.background }; columns * rows],
+        }
+    }
+
+    fn blank(&self) -> Cell {
+        Cell { glyph: b' ', foreground: self.rendition.foreground, background: self.rendition.background }
+    }
+
+    fn put_pixel(&mut self, x: usize, y: usize, value: u32) {
+        let offset = y * self.info.pitch + x * self.info.format.bytes_per_pixel;
+        let pixel = (self.buffer + offset) as *mut u8;
+        unsafe {
+            match self.info.format.bytes_per_pixel {
+                4 => ptr::write_volatile(pixel as *mut u32, value),
+                bytes => for i in 0..bytes {
+                    ptr::write_volatile(pixel.offset(i as isize), (value >> (8 * i)) as u8);
+                },
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, value: u32) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.put_pixel(x, y, value);
+            }
+        }
+    }
+
+    fn draw_cell(&mut self, row: usize, col: usize, cell: Cell) {
+        let font = self.font;
+        let bitmap = font.glyph(cell.glyph as usize);
+        let foreground = color(&self.info.format, cell.foreground);
+        let background = color(&self.info.format, cell.background);
+        let (x0, y0) = (col * font.width, row * font.height);
+        for y in 0..font.height {
+            for x in 0..font.width {
+                let value = if font.pixel(bitmap, x, y) { foreground } else { background };
+                self.put_pixel(x0 + x, y0 + y, value);
+            }
+        }
+    }
+
+    // writes the cell to the text and the screen
+    fn set_cell(&mut self, row: usize, col: usize, cell: Cell) {
+        let columns = self.columns;
+        self.cells[row * columns + col] = cell;
+        self.draw_cell(row, col, cell);
+    }
+
+    pub fn clear(&mut self) {
+        let (width, height) = (self.info.width, self.info.height);
+        let background = color(&self.info.format, self.rendition.background);
+        self.fill_rect(0, 0, width, height, background);
+        let blank = self.blank();
+        for cell in self.cells.iter_mut() {
+            *cell = blank;
+        }
+        self.column_position = 0;
+        self.row_position = 0;
+    }
+
+    fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row_position + 1 < self.rows {
+            self.row_position += 1;
+            return;
+        }
+
+        // move all text rows one row up, only the cells that change are drawn again
+        let (columns, rows, blank) = (self.columns, self.rows, self.blank());
+        for row in 0..rows {
+            for col in 0..columns {
+                let cell = if row + 1 < rows { self.cells[(row + 1) * columns + col] } else { blank };
+                if self.cells[row * columns + col] != cell {
+                    self.set_cell(row, col, cell);
+                }
+            }
+        }
+    }
+
+    fn put_glyph(&mut self, glyph: u8) {
+        if self.column_position >= self.columns {
+            self.new_line();
+        }
+        let (row, col) = (self.row_position, self.column_position);
+        let cell = Cell { glyph: glyph, foreground: self.rendition.foreground,
+                          background: self.rendition.background };
+        self.set_cell(row, col, cell);
+        self.column_position += 1;
+    }
+
+    fn put_char(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            b'\t' => {
+                self.put_glyph(b' ');
+                while self.column_position % TAB_WIDTH != 0 && self.column_position < self.columns {
+                    self.put_glyph(b' ');
+                }
+            }
+            0x08 => self.column_position = self.column_position.saturating_sub(1),
+            0x07 => {}
+            byte => self.put_glyph(byte),
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        // ASCII goes through the escape sequence parser like on the VGA console
+        for c in s.chars() {
+            if (c as u32) < 0x80 {
+                match self.parser.advance(c as u8) {
+                    Some(Action::Print(byte)) => self.put_char(byte),
+                    Some(action) => ansi::execute(self, action),
+                    None => {}
+                }
+            } else if self.parser.in_ground_state() {
+                self.put_glyph(::cp437::glyph(c));
+            } else {
+                // not allowed in an escape sequence, drop the sequence
+                self.parser.abort();
+            }
+        }
+    }
+
+    pub fn set_foreground(&mut self, foreground: Color) {
+        self.rendition.foreground = foreground as u8;
+    }
+
+    pub fn set_background(&mut self, background: Color) {
+        self.rendition.background = background as u8;
+    }
+}
+
+impl Terminal for FramebufferWriter {
+    fn size(&self) -> (usize, usize) {
+        (self.columns, self.rows)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
+    }
+
+    fn set_cursor(&mut self, row: usize, column: usize) {
+        self.row_position = row;
+        self.column_position = column;
+    }
+
+    fn saved_cursor(&mut self) -> &mut (usize, usize) {
+        &mut self.saved_position
+    }
+
+    fn rendition(&mut self) -> &mut Rendition {
+        &mut self.rendition
+    }
+
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
+        for col in start..end {
+            self.set_cell(row, col, blank);
+        }
+    }
+}
+
+// the pixel value of a VGA color number
+fn color(format: &PixelFormat, color: u8) -> u32 {
+    let (red, green, blue) = PALETTE[color as usize];
+    format.encode(red, green, blue)
+}
+
+pub struct FramebufferConsole {
+    writer: Mutex<Option<FramebufferWriter>>,
+}
+
+impl FramebufferConsole {
+    /// The writer, `None` if it is locked. For the panic screen, the code
+    /// that panicked may be holding the lock.
+    pub fn try_lock(&self) -> Option<MutexGuard<Option<FramebufferWriter>>> {
+        self.writer.try_lock()
+    }
+}
+
+impl Console for FramebufferConsole {
+    fn write_str(&self, s: &str) {
+        if let Some(ref mut writer) = *self.writer.lock() {
+            writer.write_str(s);
+        }
+    }
+
+    fn write_log(&self, level: Level, s: &str) {
+        if let Some(ref mut writer) = *self.writer.lock() {
+            let foreground = writer.rendition.foreground;
+            writer.set_foreground(level.color());
+            writer.write_str(s);
+            writer.rendition.foreground = foreground;
+        }
+    }
+}
+
+pub static FRAMEBUFFER_CONSOLE: FramebufferConsole = FramebufferConsole {
+    writer: Mutex::new(None),
+};
+
+/// Moves the kernel output to the framebuffer if GRUB set a graphics mode.
+/// The framebuffer is mapped into the vmalloc region, so memory::init must
+/// have run, and the heap has to be ready.
+pub fn init(multiboot_information_address: usize) {
+    let info = match framebuffer_info(multiboot_information_address) {
+        Some(info) if info.framebuffer_type == FramebufferType::Rgb => info,
+        // text mode, the VGA console stays
+        _ => return,
+    };
+    if info.bpp != 32 && info.bpp != 24 && info.bpp != 16 {
+        warn!("framebuffer: {} bits per pixel are not supported", info.bpp);
+        return;
+    }
+    let font = Font::parse(FONT_DATA).expect("invalid console font");
+    let buffer = match ::memory::map_write_combining(info.address, info.pitch * info.height) {
+        Some(buffer) => buffer,
+        None => {
+            warn!("framebuffer: no room to map {:#x} bytes", info.pitch * info.height);
+            return;
+        }
+    };
+
+    let mut writer = FramebufferWriter::new(buffer, info, font);
+    writer.clear();
+
+    // the VGA text buffer isn't shown in graphics mode, repeat what was printed to it so far
+    // allocated without the ring lock, a growing heap logs to the ring
+    let len = console::RING_BUFFER_CONSOLE.buffer.lock().len();
+    let mut buffered = vec![0; len];
+    let len = console::RING_BUFFER_CONSOLE.buffer.lock().read(&mut buffered);
+    buffered.truncate(len);
+    // the oldest bytes may start in the middle of a character
+    let start = buffered.iter().position(|&b| b & 0xc0 != 0x80).unwrap_or(buffered.len());
+    writer.write_str(str::from_utf8(&buffered[start..]).unwrap_or(""));
+
+    *FRAMEBUFFER_CONSOLE.writer.lock() = Some(writer);
+    console::register("framebuffer", &FRAMEBUFFER_CONSOLE, true);
+    console::set_enabled("vga", false);
+    info!("framebuffer: {}x{}x{} at {:#x}", info.width, info.height, info.bpp, info.address);
+}
+
+/// A double buffer over the framebuffer for drawing graphics, `None` in
+/// text mode. The console keeps writing to the framebuffer, flushes draw
+/// over its output.
+pub fn double_buffer() -> Option<DoubleBuffer> {
+    // the back buffer is allocated without the writer lock, a growing heap logs to the console
+    let framebuffer = FRAMEBUFFER_CONSOLE.writer.lock().as_ref()
+        .map(|writer| (writer.buffer, writer.info));
+    framebuffer.map(|(buffer, info)| unsafe {
+        DoubleBuffer::new(buffer as *mut u8, info.width, info.height, info.pitch, info.format)
+    })
+}
@@ -8,11 +8,16 @@
 #![feature(const_atomic_usize_new)]
 #![feature(global_allocator)]
 #![feature(alloc)]
-#![no_std]                  //prevent automatic linking of standard library
+#![feature(asm)]
+#![feature(used)]
+#![cfg_attr(not(test), no_std)]     //prevent automatic linking of standard library
+                                    //host unit tests (cargo test) use std
+#![cfg_attr(test, allow(dead_code, unused_macros, unused_imports))]
 
 #[macro_use]
 extern crate alloc;
 
+#[cfg(not(test))]
 extern crate rlibc;
 extern crate volatile;
 extern crate spin;
@@ -28,7 +33,24 @@ extern crate linked_list_allocator;
 
 #[macro_use]
 mod vga_buffer;
+mod cp437;
+#[macro_use]
+mod serial;
+#[macro_use]
+mod test_runner;
+mod ansi;
+mod multiboot_tags;
+mod cmdline;
+#[macro_use]
+mod log;
+mod vga_mode;
+mod dmesg;
+mod console;
 mod memory;
+mod psf;
+mod framebuffer;
+mod canvas;
+mod panic_screen;
 
 
 /*old main
@@ -113,9 +135,16 @@ pub extern fn rust_main(multiboot_information_address: usize) { //function name
 #[no_mangle]
 pub extern "C" fn rust_main(multiboot_information_address: usize) {
     // ATTENTION: we have a very small stack and no guard page
+    serial::init();
     vga_buffer::clear_screen();
     println!("Hello World{}", "!");
 
+    // the log levels are set on the command line
+    cmdline::init(multiboot_information_address);
+    log::init();
+    // the text mode is set on the command line too
+    vga_buffer::init(multiboot_information_address);
+
     // load the multiboot information address
     let boot_info = unsafe {
         multiboot2::load(multiboot_information_address)
@@ -126,9 +155,18 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
     // set up guard page and map the heap pages
     memory::init(boot_info);
 
+    // until here allocations were served by the early heap
     unsafe {
-    HEAP_ALLOCATOR.lock().init(HEAP_START, HEAP_START + HEAP_SIZE);
+    HEAP_ALLOCATOR.init(HEAP_START, HEAP_SIZE);
     }
+    ALLOCATOR.set_heap_ready();
+    vga_buffer::enable_scrollback();
+    // switches to the framebuffer console if GRUB set a graphics mode
+    framebuffer::init(multiboot_information_address);
+    debug!("early heap used {} bytes", memory::early_heap::high_water_mark());
+
+    #[cfg(feature = "kernel-test")]
+    test_runner::run_tests();
 
     use alloc::boxed::Box;
     let mut heap_test = Box::new(42);
@@ -146,6 +184,9 @@ pub extern "C" fn rust_main(multiboot_information_address: usize) {
     format!("Some String");
     }
 
+    #[cfg(feature = "slab-benchmark")]
+    memory::slab::benchmark(&ALLOCATOR);
+
     println!("It did not crash!");
 
     loop {}
@@ -169,31 +210,50 @@ fn enable_write_protect_bit() {
     unsafe { cr0_write(cr0() | Cr0::WRITE_PROTECT) };
 }
 
-// panic handler, prints PANIC when something goes wrong
-// shows which file and line the error occurred in
+// disable interrupts and halt the CPU for good
+pub fn hlt_loop() -> ! {
+    loop {
+        unsafe { asm!("cli; hlt" :::: "volatile") };
+    }
+}
+
+// panic handler, shows the panic screen when something goes wrong
+// with the file and line the error occurred in, the registers and the memory usage
+#[cfg(not(test))]
 #[lang = "panic_fmt"]
 #[no_mangle]
 pub extern fn panic_fmt(fmt: core::fmt::Arguments, file: &'static str,
     line: u32) -> !
 {
-    println!("\n\nPANIC in {} at line {}:", file, line);
-    println!("    {}", fmt);
-    loop{}
+    #[cfg(feature = "kernel-test")]
+    test_runner::test_panicked(fmt, file, line);
+
+    panic_screen::show(fmt, file, line)
 }
 
 
 // define that these functions are our lagnuage items
 // if something goes wrong and cannot reasonably be handled, the thread panics.
+#[cfg(not(test))]
 #[lang = "eh_personality"] extern fn eh_personality() {}       //used for Rust unwinding on panic!
 //#[lang = "panic_fmt"] #[no_mangle] pub extern fn panic_fmt() -> ! {loop{}}      //doesn't return (required by ! return type), put in loop
 
-use memory::heap_allocator::BumpAllocator;
-use linked_list_allocator::LockedHeap;
+use memory::heap_allocator::{BumpAllocator, GrowableHeap};
+use memory::slab::KernelAllocator;
 
 pub const HEAP_START: usize = 0o_000_001_000_000_0000;
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
-
-#[global_allocator]
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024; // the heap grows up to 64 MiB
+
+// backs all layouts that are too large for the slab allocator
+#[cfg(not(feature = "heap-debug"))]
+static HEAP_ALLOCATOR: GrowableHeap = GrowableHeap::new(HEAP_MAX_SIZE);
+#[cfg(feature = "heap-debug")]
+static HEAP_ALLOCATOR: memory::debug_heap::DebugHeap =
+    memory::debug_heap::DebugHeap::new(GrowableHeap::new(HEAP_MAX_SIZE));
+
+// host unit tests keep the allocator of std
+#[cfg_attr(not(test), global_allocator)]
+static ALLOCATOR: KernelAllocator = KernelAllocator::new();
 //#[global_allocator]
 //static HEAP_ALLOCATOR: BumpAllocator = BumpAllocator::new(HEAP_START, HEAP_START + HEAP_SIZE);
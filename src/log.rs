@@ -0,0 +1,280 @@
+// leveled kernel logging
+// error!/warn!/info!/debug!/trace! print a message tagged with its level and module path,
+// messages above the level of their module are dropped
+// the levels are set on the kernel command line, e.g.
+//
+//   log=info,memory::paging:trace,memory::slab:off
+//
+// sets the global level to info and overrides it for two modules (and their submodules)
+// the kernel log buffer (dmesg) has its own level, it keeps the messages the console filters drop
+
+use core::fmt;
+use core::mem;
+use core::str;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use vga_buffer::Color;
+
+const MAX_FILTERS: usize = 16;
+const MAX_MODULE_LENGTH: usize = 48;
+
+// level filters are stored as usize, everything up to the filter is printed
+const OFF: usize = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The color of the messages of this level on the VGA console.
+    pub fn color(&self) -> Color {
+        match *self {
+            Level::Error => Color::LightRed,
+            Level::Warn => Color::Yellow,
+            Level::Info => Color::White,
+            Level::Debug => Color::LightGray,
+            Level::Trace => Color::DarkGray,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        })
+    }
+}
+
+// parses a level name, None means off
+fn parse_level(s: &str) -> Result<Option<Level>, ()> {
+    match s {
+        "off" => Ok(None),
+        "error" => Ok(Some(Level::Error)),
+        "warn" => Ok(Some(Level::Warn)),
+        "info" => Ok(Some(Level::Info)),
+        "debug" => Ok(Some(Level::Debug)),
+        "trace" => Ok(Some(Level::Trace)),
+        _ => Err(()),
+    }
+}
+
+fn filter(level: Option<Level>) -> usize {
+    level.map(|level| level as usize).unwrap_or(OFF)
+}
+
+#[derive(Clone, Copy)]
+struct ModuleFilter {
+    module: [u8; MAX_MODULE_LENGTH],
+    len: usize,
+    level: usize,
+}
+
+impl ModuleFilter {
+    fn module(&self) -> &str {
+        str::from_utf8(&self.module[..self.len]).unwrap()
+    }
+}
+
+static MAX_LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+// the level of the messages that are stored in the kernel log buffer
+static DMESG_LEVEL: AtomicUsize = AtomicUsize::new(Level::Debug as usize);
+static FILTERS: Mutex<[Option<ModuleFilter>; MAX_FILTERS]> = Mutex::new([None; MAX_FILTERS]);
+// lets the common case without module filters skip the lock
+static FILTER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// microseconds since boot, 0 as long as no clock is set
+pub type Clock = fn() -> u64;
+static CLOCK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the level of all modules without a module filter. `None` turns
+/// logging off.
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(filter(level), Ordering::Relaxed);
+}
+
+/// Why a module filter couldn't be set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterError {
+    // longer than MAX_MODULE_LENGTH
+    ModuleTooLong,
+    // all MAX_FILTERS filters are used
+    TooManyFilters,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            FilterError::ModuleTooLong => "module path is too long",
+            FilterError::TooManyFilters => "too many module log filters",
+        })
+    }
+}
+
+/// Sets the level of a module and its submodules, e.g. `memory::paging`
+/// (without the crate name). Overrides the global level and the filters of
+/// parent modules.
+pub fn set_module_level(module: &str, level: Option<Level>) -> Result<(), FilterError> {
+    if module.len() > MAX_MODULE_LENGTH {
+        return Err(FilterError::ModuleTooLong);
+    }
+
+    let mut filters = FILTERS.lock();
+    let index = match filters.iter().position(|f| f.map_or(false, |f| f.module() == module))
+        .or_else(|| filters.iter().position(|f| f.is_none()))
+    {
+        Some(index) => index,
+        None => return Err(FilterError::TooManyFilters),
+    };
+    if filters[index].is_none() {
+        FILTER_COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut new_filter = ModuleFilter {
+        module: [0; MAX_MODULE_LENGTH],
+        len: module.len(),
+        level: filter(level),
+    };
+    new_filter.module[..module.len()].copy_from_slice(module.as_bytes());
+    filters[index] = Some(new_filter);
+    Ok(())
+}
+
+/// Sets the level of the messages that are stored in the kernel log buffer,
+/// independent of the console levels. `None` turns dmesg off.
+pub fn set_dmesg_level(level: Option<Level>) {
+    DMESG_LEVEL.store(filter(level), Ordering::Relaxed);
+}
+
+/// Sets the clock for the message timestamps.
+pub fn set_clock(clock: Clock) {
+    CLOCK.store(clock as usize, Ordering::Relaxed);
+}
+
+fn clock() -> Option<Clock> {
+    match CLOCK.load(Ordering::Relaxed) {
+        0 => None,
+        clock => Some(unsafe { mem::transmute::<usize, Clock>(clock) }),
+    }
+}
+
+// true if `path` is `module` or one of its submodules
+fn is_in_module(path: &str, module: &str) -> bool {
+    path.starts_with(module) && (path.len() == module.len() || path[module.len()..].starts_with("::"))
+}
+
+fn max_level_for(module_path: &str) -> usize {
+    let global = MAX_LEVEL.load(Ordering::Relaxed);
+    if FILTER_COUNT.load(Ordering::Relaxed) == 0 {
+        return global;
+    }
+    // the filters don't contain the crate name
+    let path = match module_path.find("::") {
+        Some(index) => &module_path[index + 2..],
+        None => "",
+    };
+    // called while the filters are changed (e.g. a panic), use the global level
+    let filters = match FILTERS.try_lock() {
+        Some(filters) => filters,
+        None => return global,
+    };
+
+    // the longest matching module wins
+    let mut best: Option<&ModuleFilter> = None;
+    for f in filters.iter().filter_map(|f| f.as_ref()) {
+        if is_in_module(path, f.module()) && best.map_or(true, |best| f.len > best.len) {
+            best = Some(f);
+        }
+    }
+    best.map_or(global, |f| f.level)
+}
+
+pub fn enabled(level: Level, module_path: &str) -> bool {
+    level as usize <= max_level_for(module_path)
+}
+
+// true if messages of the level go into the kernel log buffer
+pub fn stored(level: Level) -> bool {
+    level as usize <= DMESG_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Reads the `log` option of the kernel command line: a global level and
+/// `module:level` pairs, separated by commas.
+pub fn init() {
+    let option = match ::cmdline::option("log") {
+        Some(option) => option,
+        None => return,
+    };
+    for part in option.split(',').filter(|part| !part.is_empty()) {
+        let mut split = part.rsplitn(2, ':');
+        let level = split.next().unwrap();
+        match (split.next(), parse_level(level)) {
+            (None, Ok(level)) => set_max_level(level),
+            // the macros are defined below
+            (Some(module), Ok(level)) => if let Err(err) = set_module_level(module, level) {
+                log(Level::Warn, module_path!(), format_args!("{} in {}, ignored", err, part));
+            },
+            (_, Err(())) => log(Level::Warn, module_path!(),
+                                format_args!("unknown log level {} in {}", level, part)),
+        }
+    }
+}
+
+/// Stores the message in the kernel log buffer and prints it to the
+/// consoles, called by the logging macros.
+pub fn log(level: Level, module_path: &str, args: fmt::Arguments) {
+    if stored(level) {
+        ::dmesg::store(level, module_path, args);
+    }
+
+    match clock() {
+        Some(clock) => {
+            let us = clock();
+            ::console::write_log(level, format_args!("[{:5}.{:06}] {:5} {}: {}\n",
+                                                     us / 1_000_000, us % 1_000_000,
+                                                     level, module_path, args));
+        }
+        None => ::console::write_log(level, format_args!("{:5} {}: {}\n", level, module_path, args)),
+    }
+}
+
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => ({
+        let level = $level;
+        if $crate::log::enabled(level, module_path!()) {
+            $crate::log::log(level, module_path!(), format_args!($($arg)*));
+        } else if $crate::log::stored(level) {
+            // filtered for the consoles, dmesg still keeps it
+            $crate::dmesg::store(level, module_path!(), format_args!($($arg)*));
+        }
+    });
+}
+
+macro_rules! error {
+    ($($arg:tt)*) => (log!($crate::log::Level::Error, $($arg)*));
+}
+
+macro_rules! warn {
+    ($($arg:tt)*) => (log!($crate::log::Level::Warn, $($arg)*));
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => (log!($crate::log::Level::Info, $($arg)*));
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => (log!($crate::log::Level::Debug, $($arg)*));
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => (log!($crate::log::Level::Trace, $($arg)*));
+}
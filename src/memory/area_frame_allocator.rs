@@ -2,6 +2,20 @@
 
 use memory::{Frame, FrameAllocator};
 use multiboot2::{MemoryAreaIter, MemoryArea};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// number of handed out frames and of all usable frames, for out-of-memory reports
+static FRAMES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of allocated frames and the number of all usable frames.
+pub fn frame_statistics() -> (usize, usize) {
+    (FRAMES_ALLOCATED.load(Ordering::Relaxed), FRAMES_TOTAL.load(Ordering::Relaxed))
+}
+
+// number of returned frames that are kept for reuse, frames returned beyond that are leaked
+// the allocator can't allocate while it hands out frames, so the stack has a fixed size
+const FREE_STACK_SIZE: usize = 256;
 
 pub struct AreaFrameAllocator {
     next_free_frame: Frame,     // counter that is increased every time we return a frame
@@ -13,6 +27,10 @@ pub struct AreaFrameAllocator {
     kernel_end: Frame,
     multiboot_start: Frame,
     multiboot_end: Frame,
+
+    // numbers of returned frames, allocate_frame takes them first
+    free_frames: [usize; FREE_STACK_SIZE],
+    free_count: usize,
 }
 
 //allocate and deallocate a frame
@@ -20,6 +38,13 @@ impl FrameAllocator for AreaFrameAllocator {
 
     fn allocate_frame(&mut self) -> Option<Frame> {
 
+        // reuse a returned frame
+        if self.free_count > 0 {
+            self.free_count -= 1;
+            FRAMES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+            return Some(Frame { number: self.free_frames[self.free_count] });
+        }
+
         // Some: returns value if it exist, otherwise None
         // put self.current_area (a memory area) in area, if the area exist continue
         // if area does not exist do nothing -> no free frames left
@@ -62,6 +87,7 @@ impl FrameAllocator for AreaFrameAllocator {
         // frame is unused, increment `next_free_frame` and return it
         else {
             self.next_free_frame.number += 1;
+            FRAMES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
             return Some(frame);
         }
         // `frame` was not valid, try it again with the updated `next_free_frame`
@@ -73,7 +99,11 @@ impl FrameAllocator for AreaFrameAllocator {
     }
 
     fn deallocate_frame(&mut self, frame: Frame) {
-        // TODO (see below)
+        if self.free_count < FREE_STACK_SIZE {
+            self.free_frames[self.free_count] = frame.number;
+            self.free_count += 1;
+            FRAMES_ALLOCATED.fetch_sub(1, Ordering::Relaxed);
+        }
     }
 }
 
@@ -93,8 +123,15 @@ impl AreaFrameAllocator {
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
             multiboot_end: Frame::containing_address(multiboot_end),
+            free_frames: [0; FREE_STACK_SIZE],
+            free_count: 0,
         };
         allocator.choose_next_area();
+
+        // count the frames of all areas (the kernel and multiboot frames are included)
+        let total: usize = allocator.areas.clone()
+            .map(|area| area.length as usize / ::memory::PAGE_SIZE).sum();
+        FRAMES_TOTAL.store(total, Ordering::Relaxed);
         allocator
     }
 
@@ -0,0 +1,133 @@
+// debugging wrapper for the kernel heap (cargo feature "heap-debug")
+// every block gets a header and red zones before and after it:
+//
+//   | hole | header | front red zone | user data | back red zone |
+//
+// the red zones are checked when the block is freed, fresh blocks are filled with 0xAA
+// and freed blocks with 0xDD, so use-before-init and use-after-free show up as these patterns
+
+use alloc::heap::{Alloc, AllocErr, Layout};
+use core::mem::size_of;
+use memory::heap_allocator::{GrowableHeap, HeapStatistics, align_up};
+
+const FRESH_BYTE: u8 = 0xAA;
+const FREED_BYTE: u8 = 0xDD;
+const RED_ZONE_BYTE: u8 = 0xFD;
+const RED_ZONE_SIZE: usize = 16;
+
+// the linked list heap keeps its hole information in the first 16 bytes of a freed block,
+// the header starts behind it so that the FREED marker survives the free
+const HOLE_SIZE: usize = 16;
+
+const ALLOCATED: usize = 0xa110c8ed;
+const FREED: usize = 0xf4eed;
+
+// stored directly before the front red zone
+struct Header {
+    state: usize,
+    size: usize,
+    align: usize,
+    // offset of the user data from the start of the underlying block
+    offset: usize,
+}
+
+pub struct DebugHeap {
+    heap: GrowableHeap,
+}
+
+impl DebugHeap {
+    pub const fn new(heap: GrowableHeap) -> Self {
+        Self { heap: heap }
+    }
+
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        self.heap.init(heap_start, heap_size)
+    }
+
+    pub fn bounds(&self) -> (usize, usize) {
+        self.heap.bounds()
+    }
+
+    pub fn statistics(&self) -> Option<HeapStatistics> {
+        self.heap.statistics()
+    }
+}
+
+// offset of the user data in the underlying block
+fn user_offset(layout: &Layout) -> usize {
+    align_up(HOLE_SIZE + size_of::<Header>() + RED_ZONE_SIZE, layout.align())
+}
+
+// layout of the underlying block that holds the user data, the header and the red zones
+fn block_layout(layout: &Layout) -> Layout {
+    let size = user_offset(layout) + layout.size() + RED_ZONE_SIZE;
+    let align = ::core::cmp::max(layout.align(), size_of::<usize>());
+    Layout::from_size_align(size, align).expect("heap-debug: invalid layout")
+}
+
+unsafe fn header<'a>(ptr: *mut u8) -> &'a mut Header {
+    &mut *(ptr.offset(-((RED_ZONE_SIZE + size_of::<Header>()) as isize)) as *mut Header)
+}
+
+unsafe fn fill(start: *mut u8, len: usize, byte: u8) {
+    for i in 0..len {
+        *start.offset(i as isize) = byte;
+    }
+}
+
+// panics if a byte of the red zone was overwritten
+unsafe fn check_red_zone(ptr: *mut u8, start: *mut u8, size: usize, zone: &str) {
+    for i in 0..RED_ZONE_SIZE {
+        let byte = *start.offset(i as isize);
+        if byte != RED_ZONE_BYTE {
+            panic!("heap-debug: {} red zone of block {:#x} (size {}) overwritten at {:#x} with {:#x}",
+                   zone, ptr as usize, size, start as usize + i, byte);
+        }
+    }
+}
+
+unsafe impl<'a> Alloc for &'a DebugHeap {
+
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let mut heap = &self.heap;
+        let block = heap.alloc(block_layout(&layout))?;
+
+        let offset = user_offset(&layout);
+        let ptr = block.offset(offset as isize);
+        *header(ptr) = Header {
+            state: ALLOCATED,
+            size: layout.size(),
+            align: layout.align(),
+            offset: offset,
+        };
+        fill(ptr.offset(-(RED_ZONE_SIZE as isize)), RED_ZONE_SIZE, RED_ZONE_BYTE);
+        fill(ptr, layout.size(), FRESH_BYTE);
+        fill(ptr.offset(layout.size() as isize), RED_ZONE_SIZE, RED_ZONE_BYTE);
+        Ok(ptr)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let header = header(ptr);
+        match header.state {
+            ALLOCATED => {}
+            FREED => panic!("heap-debug: double free of {:#x} (size {})",
+                            ptr as usize, header.size),
+            _ => panic!("heap-debug: free of {:#x} (size {}) that was not allocated or has a corrupted header",
+                        ptr as usize, layout.size()),
+        }
+        if header.size != layout.size() || header.align != layout.align() {
+            panic!("heap-debug: free of {:#x} (size {}, align {}) with mismatched layout (size {}, align {})",
+                   ptr as usize, header.size, header.align, layout.size(), layout.align());
+        }
+
+        check_red_zone(ptr, ptr.offset(-(RED_ZONE_SIZE as isize)), header.size, "front");
+        check_red_zone(ptr, ptr.offset(header.size as isize), header.size, "back");
+
+        fill(ptr, header.size, FREED_BYTE);
+        header.state = FREED;
+
+        let block = ptr.offset(-(header.offset as isize));
+        let mut heap = &self.heap;
+        heap.dealloc(block, block_layout(&layout));
+    }
+}
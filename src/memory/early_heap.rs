@@ -0,0 +1,41 @@
+// early-boot heap
+// a small bump arena in .bss that serves the global allocator until memory::init has mapped the kernel heap,
+// so that memory setup can already use Box and Vec
+
+use alloc::heap::{Alloc, AllocErr, Layout};
+use spin::Once;
+use memory::heap_allocator::BumpAllocator;
+
+pub const EARLY_HEAP_SIZE: usize = 64 * 1024; // 64 KiB
+
+static mut EARLY_HEAP: [u8; EARLY_HEAP_SIZE] = [0; EARLY_HEAP_SIZE];
+static EARLY_ARENA: Once<BumpAllocator> = Once::new();
+
+fn arena() -> &'static BumpAllocator {
+    EARLY_ARENA.call_once(|| {
+        let start = unsafe { EARLY_HEAP.as_ptr() as usize };
+        BumpAllocator::new(start, start + EARLY_HEAP_SIZE)
+    })
+}
+
+// true if the block was handed out by the early heap
+pub fn contains(ptr: *mut u8) -> bool {
+    let start = unsafe { EARLY_HEAP.as_ptr() as usize };
+    let address = ptr as usize;
+    address >= start && address < start + EARLY_HEAP_SIZE
+}
+
+pub unsafe fn alloc(layout: Layout) -> Result<*mut u8, AllocErr> {
+    let mut arena = arena();
+    arena.alloc(layout)
+}
+
+pub unsafe fn dealloc(ptr: *mut u8, layout: Layout) {
+    let mut arena = arena();
+    arena.dealloc(ptr, layout)
+}
+
+/// Returns the number of bytes the early heap handed out at most.
+pub fn high_water_mark() -> usize {
+    arena().high_water_mark()
+}
@@ -2,20 +2,60 @@
 
 use alloc::heap::{Alloc, AllocErr, Layout};
 use core::sync::atomic::{AtomicUsize, Ordering};
+use linked_list_allocator::Heap;
+use spin::{Mutex, MutexGuard};
+use memory::PAGE_SIZE;
 
 #[derive(Debug)]
 
-//heap_start and heap_end fields contain the start and end address of our kernel heap
+//heap_start and heap_end fields contain the start and end address of the arena
 //next contains the next free address and is increased every allocation
+//high_water is the largest number of bytes that were in use at the same time
+//the arena can be put over any mapped region, e.g. a vmalloc range for a scratch arena during boot or ELF loading
 pub struct BumpAllocator {
     heap_start: usize,
     heap_end: usize,
     next: AtomicUsize,
+    high_water: AtomicUsize,
 }
 
 impl BumpAllocator {
+    /// Creates an arena over `heap_start..heap_end`, the range must be mapped.
     pub const fn new(heap_start: usize, heap_end: usize) -> Self {
-        Self { heap_start, heap_end, next: AtomicUsize::new(heap_start) }
+        Self { heap_start, heap_end, next: AtomicUsize::new(heap_start), high_water: AtomicUsize::new(0) }
+    }
+
+    pub fn start(&self) -> usize {
+        self.heap_start
+    }
+
+    /// Returns the number of bytes that are currently handed out.
+    pub fn used(&self) -> usize {
+        self.next.load(Ordering::Relaxed) - self.heap_start
+    }
+
+    /// Returns the largest number of bytes that were in use at the same time.
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Frees all allocations at once.
+    /// Unsafe because all blocks of the arena become invalid.
+    pub unsafe fn reset(&self) {
+        self.next.store(self.heap_start, Ordering::Relaxed);
+    }
+
+    //raise the high-water mark if `next` is beyond it
+    fn update_high_water(&self, next: usize) {
+        let used = next - self.heap_start;
+        loop {
+            let current = self.high_water.load(Ordering::Relaxed);
+            if used <= current ||
+                self.high_water.compare_and_swap(current, used, Ordering::Relaxed) == current
+            {
+                return;
+            }
+        }
     }
 }
 
@@ -41,6 +81,7 @@ loop {
                 let next_now = self.next.compare_and_swap(current_next, alloc_end, Ordering::Relaxed);
                 if next_now == current_next {
                     // next address was successfully updated, allocation succeeded
+                    self.update_high_water(alloc_end);
                     return Ok(alloc_start as *mut u8);  //returns the allocated memory start address
                 }
         } else {
@@ -49,10 +90,158 @@ loop {
     }
 }
     unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        // do nothing, leak memory
+        // only the most recent allocation can be given back, it ends at `next`
+        // everything else is leaked until the arena is reset
+        let alloc_start = ptr as usize;
+        let alloc_end = alloc_start + layout.size();
+        self.next.compare_and_swap(alloc_end, alloc_start, Ordering::Relaxed);
     }
 }
 
+/// Creates a scratch arena of `size` bytes in a fresh vmalloc range.
+/// Give the range back with `vfree(arena.start())` when the arena is no longer used.
+pub fn scratch_arena(size: usize) -> Option<BumpAllocator> {
+    use memory::paging::{WRITABLE, NO_EXECUTE};
+
+    ::memory::vmalloc(size, WRITABLE | NO_EXECUTE).map(|start| {
+        BumpAllocator::new(start, start + size)
+    })
+}
+
+
+// the heap grows by at least this many bytes at a time
+pub const HEAP_GROW_STEP: usize = 16 * PAGE_SIZE;
+
+//linked list heap that maps more pages behind its end when an allocation fails
+//grows until max_size is reached
+pub struct GrowableHeap {
+    heap: Mutex<Heap>,
+    max_size: AtomicUsize,
+    used: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStatistics {
+    pub start: usize,
+    pub size: usize,
+    pub used: usize,
+    pub largest_free_block: usize,
+}
+
+impl GrowableHeap {
+    pub const fn new(max_size: usize) -> Self {
+        Self {
+            heap: Mutex::new(Heap::empty()),
+            max_size: AtomicUsize::new(max_size),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Initializes the heap with the already mapped range `heap_start..heap_start + heap_size`.
+    pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
+        self.heap.lock().init(heap_start, heap_size);
+    }
+
+    /// Sets the size the heap may grow to.
+    pub fn set_max_size(&self, max_size: usize) {
+        self.max_size.store(max_size, Ordering::Relaxed);
+    }
+
+    // current start and size of the heap
+    pub fn bounds(&self) -> (usize, usize) {
+        let heap = self.heap.lock();
+        (heap.bottom(), heap.size())
+    }
+
+    /// Returns the heap statistics or `None` if the heap is locked right now.
+    pub fn statistics(&self) -> Option<HeapStatistics> {
+        self.heap.try_lock().map(|mut heap| HeapStatistics {
+            start: heap.bottom(),
+            size: heap.size(),
+            used: self.used.load(Ordering::Relaxed),
+            largest_free_block: largest_free_block(&mut heap),
+        })
+    }
+
+    //map new pages after the current heap end so that `layout` fits
+    //returns false if the maximum size is reached or no pages could be mapped
+    fn grow(&self, heap: &mut MutexGuard<Heap>, layout: &Layout) -> bool {
+        let max_size = self.max_size.load(Ordering::Relaxed);
+        let needed = align_up(layout.size() + layout.align(), PAGE_SIZE);
+        let by = ::core::cmp::max(needed, HEAP_GROW_STEP);
+        let by = ::core::cmp::min(by, max_size.saturating_sub(heap.size()));
+        if by < needed {
+            return false;
+        }
+
+        let heap_end = heap.top();
+        if !::memory::map_pages(heap_end, heap_end + by) {
+            return false;
+        }
+        unsafe { heap.extend(by) };
+        true
+    }
+
+    //allocate under the heap lock, `grown` is set to the old and the new heap size if the heap grew
+    fn allocate(&self, layout: &Layout, grown: &mut Option<(usize, usize)>)
+        -> Result<*mut u8, AllocErr>
+    {
+        let mut heap = self.heap.lock();
+        loop {
+            match heap.allocate_first_fit(layout.clone()) {
+                Ok(ptr) => {
+                    self.used.fetch_add(layout.size(), Ordering::Relaxed);
+                    return Ok(ptr);
+                }
+                //try again with a larger heap
+                Err(err) => {
+                    let old_size = grown.map_or(heap.size(), |(old_size, _)| old_size);
+                    if !self.grow(&mut heap, layout) {
+                        return Err(err);
+                    }
+                    *grown = Some((old_size, heap.size()));
+                }
+            }
+        }
+    }
+}
+
+unsafe impl<'a> Alloc for &'a GrowableHeap {
+
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let mut grown = None;
+        let result = self.allocate(&layout, &mut grown);
+        //logged after the heap lock is released, the log sinks may allocate
+        if let Some((old_size, size)) = grown {
+            debug!("heap: grew by {:#x} bytes to {:#x} bytes", size - old_size, size);
+        }
+        result
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.used.fetch_sub(layout.size(), Ordering::Relaxed);
+        self.heap.lock().deallocate(ptr, layout)
+    }
+}
+
+//the heap doesn't tell the size of its holes, so find the largest block that
+//can be allocated with a binary search (only used for out-of-memory reports)
+fn largest_free_block(heap: &mut Heap) -> usize {
+    let align = ::core::mem::size_of::<usize>();
+    let (mut low, mut high) = (0, heap.size());
+    while low < high {
+        let size = (low + high + 1) / 2;
+        let layout = Layout::from_size_align(size, align).unwrap();
+        match heap.allocate_first_fit(layout.clone()) {
+            Ok(ptr) => {
+                unsafe { heap.deallocate(ptr, layout) };
+                low = size;
+            }
+            Err(_) => high = size - 1,
+        }
+    }
+    low
+}
 
 /// Align downwards. Returns the greatest x with alignment `align`
 /// so that x <= addr. The alignment must be a power of 2.
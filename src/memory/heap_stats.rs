@@ -0,0 +1,231 @@
+// allocation tracking for the global allocator (cargo feature "heap-tracking")
+// counts live blocks and bytes, keeps a size histogram and remembers who allocated every live block
+// nothing in here may allocate, the tracker is called from inside the global allocator
+
+use spin::Mutex;
+
+// number of live blocks whose call site is remembered, blocks beyond that are only counted
+const TRACKED_BLOCKS: usize = 1024;
+// number of different call sites a report or snapshot can hold
+const MAX_SITES: usize = 64;
+// histogram buckets: <= 8, <= 16, ..., <= 16 KiB and larger
+const BUCKETS: usize = 13;
+// frames between the allocation call site and the global allocator
+// (__rust_alloc, the alloc::heap wrapper and the collection that allocates)
+const CALLER_DEPTH: usize = 3;
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    ptr: usize,
+    size: usize,
+    caller: usize,
+}
+
+/// Live blocks and bytes allocated from one call site.
+#[derive(Debug, Clone, Copy)]
+pub struct Site {
+    pub caller: usize,
+    pub count: usize,
+    pub bytes: usize,
+}
+
+struct Tracker {
+    live_count: usize,
+    live_bytes: usize,
+    peak_bytes: usize,
+    total_count: usize,
+    // live blocks that didn't fit into `blocks`
+    untracked: usize,
+    histogram: [usize; BUCKETS],
+    blocks: [Block; TRACKED_BLOCKS],
+}
+
+static TRACKER: Mutex<Tracker> = Mutex::new(Tracker {
+    live_count: 0,
+    live_bytes: 0,
+    peak_bytes: 0,
+    total_count: 0,
+    untracked: 0,
+    histogram: [0; BUCKETS],
+    blocks: [Block { ptr: 0, size: 0, caller: 0 }; TRACKED_BLOCKS],
+});
+
+fn bucket(size: usize) -> usize {
+    let mut bucket = 0;
+    while bucket + 1 < BUCKETS && (8 << bucket) < size {
+        bucket += 1;
+    }
+    bucket
+}
+
+// defined in boot.asm
+extern {
+    static stack_bottom: u8;
+    static stack_top: u8;
+}
+
+/// Walks up the frame pointer chain and returns the return address
+/// `CALLER_DEPTH` frames above the function this is inlined into.
+/// Needs frame pointers, returns 0 if the chain ends early or leaves the
+/// boot stack (e.g. an allocation on an interrupt stack). How many frames
+/// lie between the call site and the allocator depends on inlining, so the
+/// address may belong to a frame above or below the real call site.
+#[inline(always)]
+pub fn caller_address() -> usize {
+    let (bottom, top) = unsafe {
+        (&stack_bottom as *const u8 as usize, &stack_top as *const u8 as usize)
+    };
+    // the saved frame pointer and the return address must lie on the boot stack
+    let on_stack = |rbp: usize| rbp >= bottom && rbp + 16 <= top && rbp % 8 == 0;
+
+    let mut rbp: usize;
+    unsafe { asm!("mov %rbp, $0" : "=r"(rbp) ::: "volatile") };
+
+    for _ in 0..CALLER_DEPTH {
+        if !on_stack(rbp) {
+            return 0;
+        }
+        let next = unsafe { *(rbp as *const usize) };
+        // the frames of the callers lie above, anything else is a broken chain
+        if next <= rbp {
+            return 0;
+        }
+        rbp = next;
+    }
+    if !on_stack(rbp) {
+        return 0;
+    }
+    // the return address lies directly above the saved frame pointer
+    unsafe { *((rbp + 8) as *const usize) }
+}
+
+/// Records a new block, called by the global allocator.
+pub fn record(ptr: usize, size: usize, caller: usize) {
+    let mut tracker = TRACKER.lock();
+    tracker.live_count += 1;
+    tracker.live_bytes += size;
+    tracker.total_count += 1;
+    if tracker.live_bytes > tracker.peak_bytes {
+        tracker.peak_bytes = tracker.live_bytes;
+    }
+    tracker.histogram[bucket(size)] += 1;
+
+    match tracker.blocks.iter_mut().find(|block| block.ptr == 0) {
+        Some(block) => *block = Block { ptr: ptr, size: size, caller: caller },
+        None => tracker.untracked += 1,
+    }
+}
+
+/// Forgets a freed block, called by the global allocator.
+pub fn forget(ptr: usize, size: usize) {
+    let mut tracker = TRACKER.lock();
+    tracker.live_count -= 1;
+    tracker.live_bytes -= size;
+
+    match tracker.blocks.iter_mut().find(|block| block.ptr == ptr) {
+        Some(block) => block.ptr = 0,
+        None => tracker.untracked -= 1,
+    }
+}
+
+/// The live blocks of the heap at one point in time, grouped by call site.
+#[derive(Clone, Copy)]
+pub struct HeapSnapshot {
+    pub live_count: usize,
+    pub live_bytes: usize,
+    sites: [Site; MAX_SITES],
+    site_count: usize,
+}
+
+impl HeapSnapshot {
+
+    /// The call sites sorted by live bytes, largest first.
+    pub fn sites(&self) -> &[Site] {
+        &self.sites[..self.site_count]
+    }
+
+    fn site(&self, caller: usize) -> Option<&Site> {
+        self.sites().iter().find(|site| site.caller == caller)
+    }
+
+    /// Prints every call site that holds more blocks in `later` than in this
+    /// snapshot. Returns true if the number of live blocks grew.
+    pub fn diff(&self, later: &HeapSnapshot) -> bool {
+        println!("heap diff: {:+} blocks, {:+} bytes",
+                 later.live_count as isize - self.live_count as isize,
+                 later.live_bytes as isize - self.live_bytes as isize);
+        for site in later.sites() {
+            let (count, bytes) = self.site(site.caller)
+                .map(|s| (s.count, s.bytes)).unwrap_or((0, 0));
+            if site.count > count {
+                println!("    {:#x}: {:+} blocks, {:+} bytes", site.caller,
+                         site.count - count, site.bytes as isize - bytes as isize);
+            }
+        }
+        later.live_count > self.live_count
+    }
+}
+
+/// Groups the live blocks by call site.
+pub fn heap_snapshot() -> HeapSnapshot {
+    let tracker = TRACKER.lock();
+    let mut snapshot = HeapSnapshot {
+        live_count: tracker.live_count,
+        live_bytes: tracker.live_bytes,
+        sites: [Site { caller: 0, count: 0, bytes: 0 }; MAX_SITES],
+        site_count: 0,
+    };
+
+    for block in tracker.blocks.iter().filter(|block| block.ptr != 0) {
+        let count = snapshot.site_count;
+        match snapshot.sites[..count].iter().position(|site| site.caller == block.caller) {
+            Some(index) => {
+                snapshot.sites[index].count += 1;
+                snapshot.sites[index].bytes += block.size;
+            }
+            None if count < MAX_SITES => {
+                snapshot.sites[count] = Site { caller: block.caller, count: 1, bytes: block.size };
+                snapshot.site_count += 1;
+            }
+            // too many call sites, the rest is only part of the totals
+            None => {}
+        }
+    }
+
+    // sort by live bytes (insertion sort, there are only a few sites)
+    for i in 1..snapshot.site_count {
+        let mut j = i;
+        while j > 0 && snapshot.sites[j - 1].bytes < snapshot.sites[j].bytes {
+            snapshot.sites.swap(j - 1, j);
+            j -= 1;
+        }
+    }
+    snapshot
+}
+
+/// Prints the heap statistics and the call sites that hold the most memory.
+pub fn heap_report() {
+    let snapshot = heap_snapshot();
+    // copy the counters, printing allocates and the allocator takes the tracker lock
+    let (peak_bytes, total_count, untracked, histogram) = {
+        let tracker = TRACKER.lock();
+        (tracker.peak_bytes, tracker.total_count, tracker.untracked, tracker.histogram)
+    };
+
+    println!("heap: {} live blocks, {} live bytes, peak {} bytes, {} allocations, {} untracked",
+             snapshot.live_count, snapshot.live_bytes, peak_bytes, total_count, untracked);
+    print!("sizes:");
+    for (bucket, count) in histogram.iter().enumerate() {
+        if bucket + 1 < BUCKETS {
+            print!(" <={}:{}", 8 << bucket, count);
+        } else {
+            print!(" >{}:{}", 8 << (bucket - 1), count);
+        }
+    }
+    println!("");
+
+    println!("top call sites:");
+    for site in snapshot.sites().iter().take(10) {
+        println!("    {:#x}: {} blocks, {} bytes", site.caller, site.count, site.bytes);
+    }
+}
@@ -3,12 +3,26 @@ pub use self::paging::test_paging;
 
 pub use self::area_frame_allocator::AreaFrameAllocator;
 pub use self::paging::remap_the_kernel;
+pub use self::vmalloc::{vmalloc, vfree, map_mmio, map_write_combining};
 use self::paging::PhysicalAddress;
 use multiboot2::BootInformation;
+use spin::Mutex;
 
-mod area_frame_allocator;
+pub mod area_frame_allocator;
 mod paging;
 pub mod heap_allocator;
+pub mod slab;
+pub mod oom;
+pub mod early_heap;
+#[cfg(feature = "kernel-test")]
+mod tests;
+#[cfg(feature = "heap-debug")]
+pub mod debug_heap;
+#[cfg(feature = "heap-tracking")]
+pub mod heap_stats;
+pub mod vmalloc;
+pub mod vma;
+pub mod pat;
 
 // size of a physical page / frame
 pub const PAGE_SIZE: usize = 4096;
@@ -17,6 +31,9 @@ pub const PAGE_SIZE: usize = 4096;
 pub fn init(boot_info: &BootInformation) {
     assert_has_not_been_called!("memory::init must be called only once");
 
+    // before anything is mapped with pat::WRITE_COMBINING
+    pat::init();
+
     let memory_map_tag = boot_info.memory_map_tag().expect(
         "Memory map tag required");
     let elf_sections_tag = boot_info.elf_sections_tag().expect(
@@ -28,12 +45,12 @@ pub fn init(boot_info: &BootInformation) {
         .filter(|s| s.is_allocated()).map(|s| s.addr + s.size).max()
         .unwrap();
 
-    println!("kernel start: {:#x}, kernel end: {:#x}",
-             kernel_start,
-             kernel_end);
-    println!("multiboot start: {:#x}, multiboot end: {:#x}",
-             boot_info.start_address(),
-             boot_info.end_address());
+    debug!("kernel start: {:#x}, kernel end: {:#x}",
+           kernel_start,
+           kernel_end);
+    debug!("multiboot start: {:#x}, multiboot end: {:#x}",
+           boot_info.start_address(),
+           boot_info.end_address());
 
     let mut frame_allocator = AreaFrameAllocator::new(
         kernel_start as usize, kernel_end as usize,
@@ -52,6 +69,58 @@ pub fn init(boot_info: &BootInformation) {
     for page in Page::range_inclusive(heap_start_page, heap_end_page) {
         active_table.map(page, paging::WRITABLE, &mut frame_allocator);
     }
+
+    // hand the page table and the frame allocator over to the memory controller
+    *MEMORY_CONTROLLER.lock() = Some(MemoryController {
+        active_table: active_table,
+        frame_allocator: frame_allocator,
+        vmalloc: vmalloc::RangeAllocator::new(vmalloc::VMALLOC_START,
+            vmalloc::VMALLOC_START + vmalloc::VMALLOC_SIZE,
+            vmalloc::DEFAULT_GUARD_PAGES),
+        vmas: vma::VmaSet::new(),
+    });
+}
+
+// owns everything that is needed to change mappings after memory::init
+pub struct MemoryController {
+    active_table: paging::ActivePageTable,
+    frame_allocator: AreaFrameAllocator,
+    vmalloc: vmalloc::RangeAllocator,
+    vmas: vma::VmaSet,
+}
+
+// set by memory::init, None before
+static MEMORY_CONTROLLER: Mutex<Option<MemoryController>> = Mutex::new(None);
+
+/// Maps the pages of `start..end` to fresh frames, used by the kernel heap and
+/// the slab allocator to grow. Returns false if the memory controller is busy
+/// or not initialized.
+pub fn map_pages(start: usize, end: usize) -> bool {
+    use self::paging::Page;
+
+    // the controller might be locked by the code that made the allocator run out
+    // (e.g. vmalloc), waiting for it would deadlock
+    let mut controller = match MEMORY_CONTROLLER.try_lock() {
+        Some(controller) => controller,
+        None => return false,
+    };
+    let controller = match controller.as_mut() {
+        Some(controller) => controller,
+        None => return false,
+    };
+
+    let start_page = Page::containing_address(start);
+    let end_page = Page::containing_address(end - 1);
+    for page in Page::range_inclusive(start_page, end_page) {
+        controller.active_table.map(page, paging::WRITABLE, &mut controller.frame_allocator);
+    }
+    true
+}
+
+/// Returns a copy of the kernel VMA that contains the given address.
+pub fn find_vma(address: usize) -> Option<vma::Vma> {
+    let controller = MEMORY_CONTROLLER.lock();
+    controller.as_ref().and_then(|c| c.vmas.find(address).cloned())
 }
 
 // store the frame number
@@ -0,0 +1,93 @@
+// central out-of-memory path for the kernel heap and the frame allocator
+// a failed heap allocation gives the registered reclaim callbacks (caches etc.) a chance to free
+// memory first; if they can't, or no frame is left, it prints what was requested and how memory
+// is used, then the kernel halts
+
+use core::fmt::{self, Write};
+use alloc::heap::AllocErr;
+use spin::Mutex;
+use memory::{Frame, FrameAllocator, PAGE_SIZE, MEMORY_CONTROLLER};
+
+const MAX_RECLAIMERS: usize = 8;
+
+// a reclaim callback frees what it can and returns the number of bytes it freed
+// it runs without the heap lock and may free heap memory and call vfree
+pub type Reclaimer = fn() -> usize;
+
+static RECLAIMERS: Mutex<[Option<Reclaimer>; MAX_RECLAIMERS]> =
+    Mutex::new([None; MAX_RECLAIMERS]);
+
+/// Registers a callback that is called when the heap runs out of memory.
+pub fn register_reclaimer(reclaimer: Reclaimer) {
+    let mut reclaimers = RECLAIMERS.lock();
+    let slot = reclaimers.iter_mut().find(|slot| slot.is_none())
+        .expect("too many reclaim callbacks");
+    *slot = Some(reclaimer);
+}
+
+/// Calls all reclaim callbacks and returns the number of bytes they freed.
+/// Must not be called with the heap locked. If the memory controller is
+/// locked (e.g. vmalloc ran out of heap), the callbacks could deadlock in
+/// vfree, so none is called and 0 is returned.
+pub fn reclaim() -> usize {
+    if MEMORY_CONTROLLER.try_lock().is_none() {
+        return 0;
+    }
+    // copy the callbacks so that they can allocate and register themselves
+    let reclaimers = *RECLAIMERS.lock();
+    reclaimers.iter().filter_map(|r| *r).map(|reclaimer| reclaimer()).sum()
+}
+
+/// The heap and frame usage, shown by the out-of-memory messages and the
+/// panic screen.
+pub struct Statistics;
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match ::HEAP_ALLOCATOR.statistics() {
+            Some(stats) => writeln!(f, "    heap: {:#x} bytes at {:#x}, {:#x} bytes used, largest free block {:#x} bytes",
+                                    stats.size, stats.start, stats.used, stats.largest_free_block)?,
+            // e.g. the frame allocator ran out while the heap was growing
+            None => writeln!(f, "    heap: locked")?,
+        }
+
+        let (allocated, total) = ::memory::area_frame_allocator::frame_statistics();
+        writeln!(f, "    frames: {} of {} allocated ({} KiB free)",
+                 allocated, total, (total - allocated) * PAGE_SIZE / 1024)
+    }
+}
+
+// print the failed request and heap and frame usage, then halt
+// the heap or the memory controller may be locked, so the lock-free output of the panic screen is used
+fn out_of_memory(request: fmt::Arguments) -> ! {
+    let mut output = ::panic_screen::fatal_output();
+    let _ = write!(output, "\nOUT OF MEMORY: {}\n\n{}\n", request, Statistics);
+    let _ = write!(output, "the system is halted");
+    ::hlt_loop()
+}
+
+/// Called when a heap allocation failed even after reclaiming.
+/// Prints the failed request and the memory statistics, then halts.
+pub fn heap_exhausted(err: AllocErr) -> ! {
+    match err {
+        AllocErr::Exhausted { request } => {
+            out_of_memory(format_args!("heap allocation of {} bytes (align {}) failed",
+                                       request.size(), request.align()))
+        }
+        AllocErr::Unsupported { details } => {
+            out_of_memory(format_args!("unsupported heap allocation: {}", details))
+        }
+    }
+}
+
+/// Allocates a frame. Prints the memory statistics and halts if no frame is
+/// left. The reclaim callbacks aren't called, the caller holds the memory
+/// controller (and maybe the heap lock) the callbacks would need.
+pub fn allocate_frame<A>(allocator: &mut A) -> Frame
+    where A: FrameAllocator
+{
+    match allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => out_of_memory(format_args!("no free frame left")),
+    }
+}
@@ -0,0 +1,126 @@
+//temporary mappings of frames through a pool of slots (kmap)
+//several frames can be mapped at the same time, for example to copy from one physical frame to another
+
+use super::{Page, VirtualAddress, Mapper, ActivePageTable};
+use super::entry::*;
+use super::table::{Table, Level1};
+use memory::{Frame, FrameAllocator};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+// the kmap window starts at 2GiB and lives in a single P1 table
+// page 0 of the window maps that P1 table itself, the slots follow behind it
+pub const KMAP_START: usize = 0o_000_002_000_000_0000;
+pub const KMAP_SLOTS: usize = 16;
+
+// bit n is set if slot n is in use
+static USED_SLOTS: AtomicUsize = AtomicUsize::new(0);
+
+// the P1 table of the window, reachable through the window itself
+fn window_table() -> &'static mut Table<Level1> {
+    unsafe { &mut *(KMAP_START as *mut Table<Level1>) }
+}
+
+fn slot_page(slot: usize) -> Page {
+    Page::containing_address(KMAP_START + (slot + 1) * ::memory::PAGE_SIZE)
+}
+
+/// Creates the P1 table of the kmap window in the active table and maps it
+/// into the first page of the window.
+pub fn init<A>(active_table: &mut ActivePageTable, allocator: &mut A)
+    where A: FrameAllocator
+{
+    use x86_64::instructions::tlb;
+    use x86_64::VirtualAddress;
+
+    assert_has_not_been_called!("kmap::init must be called only once");
+
+    let table_page = Page::containing_address(KMAP_START);
+    let (p4, access) = active_table.tables();
+    let p2 = p4.next_table_create(table_page.p4_index(), allocator, access)
+        .next_table_create(table_page.p3_index(), allocator, access);
+
+    // the P2 entry now points to the (zeroed) P1 table of the window
+    p2.next_table_create(table_page.p2_index(), allocator, access);
+    let p1_frame = p2[table_page.p2_index()].pointed_frame().unwrap();
+
+    // map the P1 table into its own window, from now on the slots can be
+    // changed without going through the recursive mapping
+    let p1 = p2.next_table_mut(table_page.p2_index(), access).unwrap();
+    p1[table_page.p1_index()].set(p1_frame, PRESENT | WRITABLE | NO_EXECUTE);
+    tlb::flush(VirtualAddress(KMAP_START));
+}
+
+/// Makes the kmap window available in the page table of `mapper`.
+/// The window's P1 table is shared, so slots mapped later are visible in
+/// every table the window was installed in.
+pub fn install<A>(mapper: &mut Mapper, allocator: &mut A)
+    where A: FrameAllocator
+{
+    let table_page = Page::containing_address(KMAP_START);
+    let p1_frame = window_table()[table_page.p1_index()].pointed_frame()
+        .expect("kmap window is not initialized");
+
+    let (p4, access) = mapper.tables();
+    let p2 = p4.next_table_create(table_page.p4_index(), allocator, access)
+        .next_table_create(table_page.p3_index(), allocator, access);
+    assert!(p2[table_page.p2_index()].is_unused(),
+            "kmap window is already in use");
+    p2[table_page.p2_index()].set(p1_frame, PRESENT | WRITABLE);
+}
+
+/// Maps the given frame into a free slot of the kmap window. The slot is
+/// unmapped again when the returned guard is dropped.
+pub fn kmap(frame: Frame) -> KmapGuard {
+    use x86_64::instructions::tlb;
+    use x86_64::VirtualAddress;
+
+    // claim a free slot, try again if another thread was faster
+    let slot = loop {
+        let used = USED_SLOTS.load(Ordering::Relaxed);
+        let slot = (!used).trailing_zeros() as usize;
+        assert!(slot < KMAP_SLOTS, "no free kmap slots");
+
+        if USED_SLOTS.compare_and_swap(used, used | (1 << slot), Ordering::Acquire) == used {
+            break slot;
+        }
+    };
+
+    let page = slot_page(slot);
+    window_table()[page.p1_index()].set(frame, PRESENT | WRITABLE | NO_EXECUTE);
+    tlb::flush(VirtualAddress(page.start_address()));
+
+    KmapGuard { slot: slot }
+}
+
+/// A frame that is temporarily mapped into the kmap window.
+pub struct KmapGuard {
+    slot: usize,
+}
+
+impl KmapGuard {
+
+    /// Returns the virtual address the frame is mapped to.
+    pub fn address(&self) -> VirtualAddress {
+        slot_page(self.slot).start_address()
+    }
+
+    /// Interprets the mapped frame as a page table.
+    // we return a level 1 table since it forbids calling the next_table methods
+    pub fn table(&mut self) -> &mut Table<Level1> {
+        unsafe { &mut *(self.address() as *mut Table<Level1>) }
+    }
+}
+
+impl Drop for KmapGuard {
+    fn drop(&mut self) {
+        use x86_64::instructions::tlb;
+        use x86_64::VirtualAddress;
+
+        let page = slot_page(self.slot);
+        window_table()[page.p1_index()].set_unused();
+        tlb::flush(VirtualAddress(page.start_address()));
+
+        // give the slot back
+        USED_SLOTS.fetch_and(!(1 << self.slot), Ordering::Release);
+    }
+}
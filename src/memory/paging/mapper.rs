@@ -3,21 +3,33 @@
 
 use super::{VirtualAddress, PhysicalAddress, Page, ENTRY_COUNT};
 use super::entry::*;
-use super::table::{self, Table, Level4, Level1};
+use super::table::{self, Table, Level4, Level1, TableAccess, RecursiveAccess};
 use memory::{PAGE_SIZE, Frame, FrameAllocator};
 use core::ptr::Unique;
 
-pub struct Mapper {
+//the tables are reached through `access`, the kernel uses the recursive mapping
+pub struct Mapper<T: TableAccess = RecursiveAccess> {
     p4: Unique<Table<Level4>>,
+    access: T,
 }
 
-//mapping functions from ActivePageTable
-//with function is removed
 impl Mapper {
 
     pub unsafe fn new() -> Mapper {
+        Mapper::with_access(table::P4, RecursiveAccess)
+    }
+}
+
+//mapping functions from ActivePageTable
+//with function is removed
+impl<T> Mapper<T> where T: TableAccess {
+
+    /// Creates a mapper for the P4 table at `p4`, the other tables are found
+    /// through `access`.
+    pub unsafe fn with_access(p4: *mut Table<Level4>, access: T) -> Mapper<T> {
         Mapper {
-            p4: Unique::new_unchecked(table::P4),
+            p4: Unique::new_unchecked(p4),
+            access: access,
         }
     }
 
@@ -29,6 +41,11 @@ impl Mapper {
         unsafe { self.p4.as_mut() }
     }
 
+    // the P4 table together with the access needed to walk down from it
+    pub fn tables(&mut self) -> (&mut Table<Level4>, &T) {
+        (unsafe { self.p4.as_mut() }, &self.access)
+    }
+
     // translates virtual address to physical address
     /// Returns `None` if the address is not mapped.
     pub fn translate(&self, virtual_address: VirtualAddress) -> Option<PhysicalAddress> {
@@ -41,7 +58,8 @@ impl Mapper {
     pub fn translate_page(&self, page: Page) -> Option<Frame> {
 
         // unsafe to convert the P4 pointer to a reference
-        let p3 = self.p4().next_table(page.p4_index());
+        let access = &self.access;
+        let p3 = self.p4().next_table(page.p4_index(), access);
 
         // calculates corresponding frame if huge pages are used
         let huge_page = || {
@@ -58,7 +76,7 @@ impl Mapper {
                       });
                   }
               }
-              if let Some(p2) = p3.next_table(page.p3_index()) {
+              if let Some(p2) = p3.next_table(page.p3_index(), access) {
                   let p2_entry = &p2[page.p2_index()];
                   // 2MiB page?
                   if let Some(start_frame) = p2_entry.pointed_frame() {
@@ -77,8 +95,8 @@ impl Mapper {
 
         // use the and_then function to go through the four table levels to find the frame
         // if some entry is None, we check if the page is a huge page
-        p3.and_then(|p3| p3.next_table(page.p3_index()))
-          .and_then(|p2| p2.next_table(page.p2_index()))
+        p3.and_then(|p3| p3.next_table(page.p3_index(), access))
+          .and_then(|p2| p2.next_table(page.p2_index(), access))
           .and_then(|p1| p1[page.p1_index()].pointed_frame())
           .or_else(huge_page)
     }
@@ -91,10 +109,10 @@ impl Mapper {
     {
 
         // return next table if it exist or create a new one
-        let p4 = self.p4_mut();
-        let mut p3 = p4.next_table_create(page.p4_index(), allocator);
-        let mut p2 = p3.next_table_create(page.p3_index(), allocator);
-        let mut p1 = p2.next_table_create(page.p2_index(), allocator);
+        let (p4, access) = self.tables();
+        let mut p3 = p4.next_table_create(page.p4_index(), allocator, access);
+        let mut p2 = p3.next_table_create(page.p3_index(), allocator, access);
+        let mut p1 = p2.next_table_create(page.p2_index(), allocator, access);
 
         // assert that the page is unmapped and set the present flag
         assert!(p1[page.p1_index()].is_unused());
@@ -107,7 +125,7 @@ impl Mapper {
     pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A)
     where A: FrameAllocator
     {
-    let frame = allocator.allocate_frame().expect("out of memory");
+    let frame = ::memory::oom::allocate_frame(allocator);
     self.map_to(page, frame, flags, allocator)
     }
 
@@ -127,23 +145,207 @@ impl Mapper {
     pub fn unmap<A>(&mut self, page: Page, allocator: &mut A)
         where A: FrameAllocator
     {
-        use x86_64::instructions::tlb;
-        use x86_64::VirtualAddress;
+        let frame = self.unmap_frame(page);
+        allocator.deallocate_frame(frame);
+    }
 
+    /// Unmaps the given page and returns the frame it pointed to, the frame
+    /// is not freed (device memory, frames of the kernel image).
+    pub fn unmap_frame(&mut self, page: Page) -> Frame {
         assert!(self.translate(page.start_address()).is_some());
 
-        let p1 = self.p4_mut()
-                    .next_table_mut(page.p4_index())
-                    .and_then(|p3| p3.next_table_mut(page.p3_index()))
-                    .and_then(|p2| p2.next_table_mut(page.p2_index()))
-                    .expect("mapping code does not support huge pages");
+        let frame = {
+            let p1 = self.p1_mut(page).expect("mapping code does not support huge pages");
 
-        let frame = p1[page.p1_index()].pointed_frame().unwrap();
-        p1[page.p1_index()].set_unused();
+            let frame = p1[page.p1_index()].pointed_frame().unwrap();
+            p1[page.p1_index()].set_unused();
+            frame
+        };
 
-        tlb::flush(VirtualAddress(page.start_address()));
+        self.access.flush(page);
         // TODO free p(1,2,3) table if empty
-        //allocator.deallocate_frame(frame);
+        frame
+    }
+
+    /// Replaces the flags of a mapped page. The `PRESENT` flag is kept.
+    pub fn set_flags(&mut self, page: Page, flags: EntryFlags) {
+        {
+            let p1 = self.p1_mut(page).expect("mapping code does not support huge pages");
+            let frame = p1[page.p1_index()].pointed_frame().expect("page is not mapped");
+            p1[page.p1_index()].set(frame, flags | PRESENT);
+        }
+        self.access.flush(page);
+    }
+
+    // the P1 table that maps the page, None if it doesn't exist or a huge page is used
+    fn p1_mut(&mut self, page: Page) -> Option<&mut Table<Level1>> {
+        let (p4, access) = self.tables();
+        p4.next_table_mut(page.p4_index(), access)
+          .and_then(|p3| p3.next_table_mut(page.p3_index(), access))
+          .and_then(|p2| p2.next_table_mut(page.p2_index(), access))
+    }
+}
+
+// host tests (cargo test), the tables live in a Vec of simulated 4 KiB frames
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::table::TableAccess;
+    use std::cell::UnsafeCell;
+    use std::vec::Vec;
+
+    // frame n of the simulated physical memory is frames[n]
+    struct SimulatedMemory {
+        frames: Vec<UnsafeCell<[u64; ENTRY_COUNT]>>,
+    }
+
+    impl SimulatedMemory {
+        fn new(frame_count: usize) -> SimulatedMemory {
+            let mut frames = Vec::new();
+            for _ in 0..frame_count {
+                frames.push(UnsafeCell::new([0; ENTRY_COUNT]));
+            }
+            SimulatedMemory { frames: frames }
+        }
+
+        // a mapper whose P4 table is frame 0
+        fn mapper(&self) -> Mapper<&SimulatedMemory> {
+            unsafe { Mapper::with_access(self.frames[0].get() as *mut _, self) }
+        }
+    }
+
+    impl<'a> TableAccess for &'a SimulatedMemory {
+        fn next_table_address(&self, _table_address: usize, _index: usize, frame: Frame) -> usize {
+            self.frames[frame.number].get() as usize
+        }
+
+        fn flush(&self, _page: Page) {}
+    }
+
+    // hands out the frames after the P4 table
+    struct SimulatedFrameAllocator {
+        next: usize,
+        end: usize,
+        freed: Vec<usize>,
     }
 
+    impl FrameAllocator for SimulatedFrameAllocator {
+        fn allocate_frame(&mut self) -> Option<Frame> {
+            if self.next < self.end {
+                self.next += 1;
+                Some(Frame { number: self.next - 1 })
+            } else {
+                None
+            }
+        }
+
+        fn deallocate_frame(&mut self, frame: Frame) {
+            self.freed.push(frame.number);
+        }
+    }
+
+    fn setup(frame_count: usize) -> (SimulatedMemory, SimulatedFrameAllocator) {
+        let allocator = SimulatedFrameAllocator { next: 1, end: frame_count, freed: Vec::new() };
+        (SimulatedMemory::new(frame_count), allocator)
+    }
+
+    #[test]
+    fn map_to_creates_tables() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+
+        let addr = 42 * 512 * 512 * 4096 + 0x123;
+        assert_eq!(mapper.translate(addr), None);
+        mapper.map_to(Page::containing_address(addr), Frame { number: 1000 },
+                      WRITABLE, &mut allocator);
+
+        // P3, P2 and P1 were created
+        assert_eq!(allocator.next, 4);
+        assert_eq!(mapper.translate(addr), Some(1000 * PAGE_SIZE + 0x123));
+
+        // the next page in the same P1 table needs no new tables
+        mapper.map_to(Page::containing_address(addr + PAGE_SIZE), Frame { number: 1001 },
+                      WRITABLE, &mut allocator);
+        assert_eq!(allocator.next, 4);
+    }
+
+    #[test]
+    fn unmap() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+
+        let page = Page::containing_address(0x4000_0000);
+        mapper.map_to(page, Frame { number: 7 }, WRITABLE, &mut allocator);
+        assert!(mapper.translate_page(page).is_some());
+
+        mapper.unmap(page, &mut allocator);
+        assert_eq!(mapper.translate_page(page), None);
+        assert_eq!(mapper.translate(0x4000_0000), None);
+        assert_eq!(allocator.freed, [7]);
+    }
+
+    #[test]
+    fn unmap_frame_keeps_frame() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+
+        let page = Page::containing_address(0x4000_0000);
+        mapper.map_to(page, Frame { number: 7 }, WRITABLE, &mut allocator);
+        assert_eq!(mapper.unmap_frame(page), Frame { number: 7 });
+        assert_eq!(mapper.translate_page(page), None);
+        assert!(allocator.freed.is_empty());
+    }
+
+    #[test]
+    fn huge_page_translation() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+
+        // 1GiB page: P4 entry 0 -> P3, P3 entry 1 is huge
+        {
+            let (p4, access) = mapper.tables();
+            let p3 = p4.next_table_create(0, &mut allocator, access);
+            p3[1].set(Frame { number: 512 * 512 }, PRESENT | HUGE_PAGE);
+        }
+        assert_eq!(mapper.translate(0x4000_0000 + 0x12_3456),
+                   Some(0x4000_0000 + 0x12_3456));
+
+        // 2MiB page: P3 entry 0 -> P2, P2 entry 3 is huge
+        {
+            let (p4, access) = mapper.tables();
+            let p2 = p4.next_table_create(0, &mut allocator, access)
+                       .next_table_create(0, &mut allocator, access);
+            p2[3].set(Frame { number: 5 * 512 }, PRESENT | HUGE_PAGE);
+        }
+        assert_eq!(mapper.translate(3 * 0x20_0000 + 0x1234),
+                   Some(5 * 0x20_0000 + 0x1234));
+    }
+
+    #[test]
+    #[should_panic]
+    fn map_into_huge_page_panics() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+        {
+            let (p4, access) = mapper.tables();
+            let p3 = p4.next_table_create(0, &mut allocator, access);
+            p3[1].set(Frame { number: 512 * 512 }, PRESENT | HUGE_PAGE);
+        }
+        mapper.map_to(Page::containing_address(0x4000_0000), Frame { number: 7 },
+                      WRITABLE, &mut allocator);
+    }
+
+    #[test]
+    fn set_flags() {
+        let (memory, mut allocator) = setup(8);
+        let mut mapper = memory.mapper();
+
+        let page = Page::containing_address(0x1234_5000);
+        mapper.map_to(page, Frame { number: 9 }, WRITABLE, &mut allocator);
+        mapper.set_flags(page, NO_EXECUTE);
+
+        let flags = mapper.p1_mut(page).unwrap()[page.p1_index()].flags();
+        assert_eq!(flags, PRESENT | NO_EXECUTE);
+        assert_eq!(mapper.translate_page(page), Some(Frame { number: 9 }));
+    }
 }
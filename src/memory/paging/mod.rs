@@ -7,15 +7,15 @@ use memory::FrameAllocator;
 use self::table::{Table, Level4};
 use memory::PAGE_SIZE;
 use memory::Frame;
-use self::temporary_page::TemporaryPage;
+use self::kmap::kmap;
 use core::ops::{Deref, DerefMut};
 use multiboot2::BootInformation;
 use memory::paging::table::P4;
 
 mod entry;
 mod table;
-mod temporary_page;
 mod mapper;
+pub mod kmap;
 
 const ENTRY_COUNT: usize = 512;     // number of entries per table
 
@@ -116,7 +116,6 @@ impl ActivePageTable {
     //temporary change the recursive mapping to point to the inactive P4 table
     pub fn with<F>(&mut self,
                    table: &mut InactivePageTable,
-                   temporary_page: &mut temporary_page::TemporaryPage, // new
                    f: F)
                // fnonce allows captured variables to be moved out from the closure environment
                //closure gets a Mapper as argument instead of ActivePageTable
@@ -131,8 +130,9 @@ impl ActivePageTable {
         let backup = Frame::containing_address(
             control_regs::cr3().0 as usize);
 
-        // map temporary_page to current p4 table
-        let p4_table = temporary_page.map_table_frame(backup.clone(), self);
+        // map the current p4 table into a kmap slot
+        let mut p4_guard = kmap(backup.clone());
+        let p4_table = p4_guard.table();
 
         // overwrite recursive mapping
         // overwrite P4 entry and point it to the inactive table frame
@@ -148,8 +148,7 @@ impl ActivePageTable {
         p4_table[511].set(backup, PRESENT | WRITABLE);
         tlb::flush_all();
     }
-
-        temporary_page.unmap(self);
+        // the kmap slot is unmapped when p4_guard goes out of scope
     }
 
     // switch tables
@@ -181,18 +180,17 @@ impl InactivePageTable {
 
     //to zero the table
     //we can now create valid inactive page tables
-    pub fn new(frame: Frame, active_table: &mut ActivePageTable, temporary_page: &mut TemporaryPage) -> InactivePageTable
+    pub fn new(frame: Frame) -> InactivePageTable
     {
-        {   //map page to page table
-            let table = temporary_page.map_table_frame(frame.clone(),
-                active_table);
+        {   //map page table frame into a kmap slot, unmapped again at the end of the block
+            let mut guard = kmap(frame.clone());
+            let table = guard.table();
 
             // now we are able to zero the table
             table.zero();
             // set up recursive mapping for the table
             table[511].set(frame.clone(), PRESENT | WRITABLE);
         }
-        temporary_page.unmap(active_table);
 
         InactivePageTable { p4_frame: frame }
     }
@@ -203,16 +201,15 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation)
     -> ActivePageTable
     where A: FrameAllocator
 {
-    let mut temporary_page = TemporaryPage::new(Page { number: 0xcafebabe },
-        allocator);
-
     let mut active_table = unsafe { ActivePageTable::new() };
+    kmap::init(&mut active_table, allocator);
+
     let mut new_table = {
-        let frame = allocator.allocate_frame().expect("no more frames");
-        InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
+        let frame = ::memory::oom::allocate_frame(allocator);
+        InactivePageTable::new(frame)
     };
 
-    active_table.with(&mut new_table, &mut temporary_page, |mapper| {
+    active_table.with(&mut new_table, |mapper| {
         let elf_sections_tag = boot_info.elf_sections_tag()
             .expect("Memory map tag required");
 
@@ -228,8 +225,8 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation)
             assert!(section.start_address() % PAGE_SIZE == 0,
                     "sections need to be page aligned");
 
-            println!("mapping section at addr: {:#x}, size: {:#x}",
-                section.addr, section.size);
+            trace!("mapping section at addr: {:#x}, size: {:#x}",
+                   section.addr, section.size);
 
             let flags = EntryFlags::from_elf_section_flags(section);
 
@@ -251,17 +248,20 @@ pub fn remap_the_kernel<A>(allocator: &mut A, boot_info: &BootInformation)
             mapper.identity_map(frame, PRESENT, allocator);
         }
 
+        // keep the kmap window in the new table
+        kmap::install(mapper, allocator);
     });
 
     let old_table = active_table.switch(new_table);
-    println!("NEW TABLE!!!");
+    debug!("switched to the new page table");
 
     // turn the old p4 page into a guard page
     let old_p4_page = Page::containing_address(
       old_table.p4_frame.start_address()
     );
-    active_table.unmap(old_p4_page, allocator);
-    println!("guard page at {:#x}", old_p4_page.start_address());
+    // the frame lies in the .bss of the kernel, it is not handed out again
+    active_table.unmap_frame(old_p4_page);
+    debug!("guard page at {:#x}", old_p4_page.start_address());
 
     active_table
 }
@@ -3,13 +3,42 @@
 use core::marker::PhantomData;      // needed since unused type parameters are not allowed in Rust
 use memory::paging::entry::*;
 use memory::paging::ENTRY_COUNT;
-use memory::FrameAllocator;
+use memory::paging::Page;
+use memory::{Frame, FrameAllocator};
 use core::ops::{Index, IndexMut};
 
 
 // P4 table is available at 0xfffffffffffff000
 pub const P4: *mut Table<Level4> = 0xffffffff_fffff000 as *mut _;
 
+// tells the page table code where the tables of a hierarchy can be found
+// the kernel reaches them through the recursive mapping, the host tests through simulated frames
+pub trait TableAccess {
+    /// Returns the address of the table in `frame`, which is the frame
+    /// entry `index` of the table at `table_address` points to.
+    fn next_table_address(&self, table_address: usize, index: usize, frame: Frame) -> usize;
+
+    /// Called after the mapping of the page was changed.
+    fn flush(&self, page: Page);
+}
+
+// access through the recursive P4 entry 511
+pub struct RecursiveAccess;
+
+impl TableAccess for RecursiveAccess {
+    // formula to calculate next address, the address of next page table
+    fn next_table_address(&self, table_address: usize, index: usize, _frame: Frame) -> usize {
+        (table_address << 9) | (index << 12)
+    }
+
+    fn flush(&self, page: Page) {
+        use x86_64::instructions::tlb;
+        use x86_64::VirtualAddress;
+
+        tlb::flush(VirtualAddress(page.start_address()));
+    }
+}
+
 pub struct Table<L: TableLevel> {
     // array of 512 entries
     // Entry - what it contains
@@ -51,43 +80,50 @@ impl<L> Table<L> where L: HierarchicalLevel {
     // convert addresses to raw pointers throu as
     // convert addresses to Rust references through mut
     // return the table of the next level (P3 for P4, P1 for P2 and so on..)
-    pub fn next_table(&self, index: usize) -> Option<&Table<L::NextLevel>> {
+    pub fn next_table<T>(&self, index: usize, access: &T) -> Option<&Table<L::NextLevel>>
+        where T: TableAccess
+    {
         //if address at place index exists -> make it into a reference
-        self.next_table_address(index).map(|address| unsafe { &*(address as *const _) })
+        self.next_table_address(index, access).map(|address| unsafe { &*(address as *const _) })
     }
-    pub fn next_table_mut(&mut self, index: usize) -> Option<&mut Table<L::NextLevel>> {
-        self.next_table_address(index).map(|address| unsafe { &mut *(address as *mut _) })
+    pub fn next_table_mut<T>(&mut self, index: usize, access: &T) -> Option<&mut Table<L::NextLevel>>
+        where T: TableAccess
+    {
+        self.next_table_address(index, access).map(|address| unsafe { &mut *(address as *mut _) })
     }
 
     // calculate the next page table address
-    fn next_table_address(&self, index: usize) -> Option<usize> {
+    fn next_table_address<T>(&self, index: usize, access: &T) -> Option<usize>
+        where T: TableAccess
+    {
         let entry_flags = self[index].flags();
         // next table address is only valid if the corresponding entry is present and does not create a huge page
         if entry_flags.contains(PRESENT) && !entry_flags.contains(HUGE_PAGE) {
             let table_address = self as *const _ as usize;
-            // formula to calculate next address, the address of next page table
-            Some((table_address << 9) | (index << 12))
+            let frame = self[index].pointed_frame().unwrap();
+            Some(access.next_table_address(table_address, index, frame))
         } else {
             None
         }
     }
 
     // return next table if it exists or create a new one
-    pub fn next_table_create<A>(&mut self, index: usize, allocator: &mut A) -> &mut Table<L::NextLevel>
-    where A: FrameAllocator
+    pub fn next_table_create<A, T>(&mut self, index: usize, allocator: &mut A, access: &T)
+        -> &mut Table<L::NextLevel>
+    where A: FrameAllocator, T: TableAccess
 {
     // if there does not exist a next table
-    if self.next_table(index).is_none() {
+    if self.next_table(index, access).is_none() {
         assert!(!self.entries[index].flags().contains(HUGE_PAGE),
                 "mapping code does not support huge pages");
         // allocate frames
-        let frame = allocator.allocate_frame().expect("no frames available");
+        let frame = ::memory::oom::allocate_frame(allocator);
         // set the present and writeable bits
         self.entries[index].set(frame, PRESENT | WRITABLE);
         // set all entries to unused
-        self.next_table_mut(index).unwrap().zero();
+        self.next_table_mut(index, access).unwrap().zero();
     }
-    self.next_table_mut(index).unwrap()
+    self.next_table_mut(index, access).unwrap()
     }
 }
 
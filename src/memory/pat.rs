@@ -0,0 +1,51 @@
+// page attribute table (PAT)
+// the PWT, PCD and PAT bits of a page table entry select one of eight memory types in the
+// IA32_PAT MSR; by default the entries are WB, WT, UC-, UC, WB, WT, UC-, UC
+// entry 4 (only the PAT bit set) is reprogrammed to write-combining, so that framebuffers get
+// fast streaming writes; no other flag combination selects it, entries 0-3 keep their types
+// every x86_64 CPU has a PAT, so there is no CPUID check
+
+use x86_64::registers::msr::{rdmsr, wrmsr};
+use x86_64::registers::control_regs::{cr0, cr0_write, Cr0};
+use x86_64::instructions::tlb;
+use memory::paging::{EntryFlags, HUGE_PAGE};
+
+const IA32_PAT: u32 = 0x277;
+// interrupt flag in RFLAGS
+const INTERRUPT_FLAG: u64 = 1 << 9;
+
+// memory types of the PAT entries
+const WRITE_COMBINING_TYPE: u64 = 0x01;
+const WRITE_COMBINING_ENTRY: u64 = 4;
+
+/// The flags that select write-combining after `init`. Bit 7 of a P1 entry
+/// is the PAT bit (it is the huge page bit only in P2 and P3 entries).
+pub const WRITE_COMBINING: EntryFlags = HUGE_PAGE;
+
+/// Programs the write-combining entry. Follows the sequence of the Intel SDM
+/// (11.12.4): no cached data or translation may use the old memory type.
+pub fn init() {
+    let shift = WRITE_COMBINING_ENTRY * 8;
+    unsafe {
+        let rflags: u64;
+        asm!("pushfq; pop $0" : "=r"(rflags) ::: "volatile");
+        asm!("cli" :::: "volatile");
+
+        // enter no-fill cache mode and flush the caches and the TLB
+        let old_cr0 = cr0();
+        cr0_write((old_cr0 | Cr0::CACHE_DISABLE) - Cr0::NOT_WRITE_THROUGH);
+        asm!("wbinvd" :::: "volatile");
+        tlb::flush_all();
+
+        let pat = rdmsr(IA32_PAT);
+        wrmsr(IA32_PAT, (pat & !(0xff << shift)) | (WRITE_COMBINING_TYPE << shift));
+
+        asm!("wbinvd" :::: "volatile");
+        tlb::flush_all();
+        cr0_write(old_cr0);
+
+        if rflags & INTERRUPT_FLAG != 0 {
+            asm!("sti" :::: "volatile");
+        }
+    }
+}
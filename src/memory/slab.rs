@@ -0,0 +1,232 @@
+// slab allocator for small kernel objects
+// every size class from 8 to 2048 bytes keeps a free list of equally sized objects,
+// so allocating and freeing is a list pop/push instead of a first fit search through the heap
+// larger layouts fall back to the linked list heap
+
+use alloc::heap::{Alloc, AllocErr, Layout};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use memory::PAGE_SIZE;
+
+// the slab pages are mapped into this region, 1GiB starting at 3GiB
+pub const SLAB_START: usize = 0o_000_003_000_000_0000;
+pub const SLAB_SIZE: usize = 1024 * 1024 * 1024;
+
+// object sizes of the classes, all powers of two
+const CLASS_SIZES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+// returns the index of the smallest class that fits the layout
+// objects are aligned to their size since slabs are page aligned
+fn class_index(layout: &Layout) -> Option<usize> {
+    let size = ::core::cmp::max(layout.size(), layout.align());
+    CLASS_SIZES.iter().position(|&class_size| class_size >= size)
+}
+
+// a free object stores the address of the next free object of its class
+struct FreeObject {
+    next: usize,
+}
+
+// free list of one size class, 0 marks the end of the list
+struct SizeClass {
+    free: usize,
+}
+
+pub struct SlabAllocator {
+    classes: [Mutex<SizeClass>; 9],
+    // start of the next unused page of the slab region
+    next_page: AtomicUsize,
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        Self {
+            classes: [
+                Mutex::new(SizeClass { free: 0 }), Mutex::new(SizeClass { free: 0 }),
+                Mutex::new(SizeClass { free: 0 }), Mutex::new(SizeClass { free: 0 }),
+                Mutex::new(SizeClass { free: 0 }), Mutex::new(SizeClass { free: 0 }),
+                Mutex::new(SizeClass { free: 0 }), Mutex::new(SizeClass { free: 0 }),
+                Mutex::new(SizeClass { free: 0 }),
+            ],
+            next_page: AtomicUsize::new(SLAB_START),
+        }
+    }
+
+    // true if the address lies in a slab page
+    pub fn contains(&self, address: usize) -> bool {
+        address >= SLAB_START && address < self.next_page.load(Ordering::Relaxed)
+    }
+
+    //pop an object from the free list of the class, a new slab is created if the list is empty
+    fn alloc(&self, class: usize) -> Option<*mut u8> {
+        let mut class_list = self.classes[class].lock();
+        if class_list.free == 0 {
+            class_list.free = match self.new_slab(CLASS_SIZES[class]) {
+                Some(first) => first,
+                None => return None,
+            };
+        }
+
+        let object = class_list.free;
+        class_list.free = unsafe { (*(object as *const FreeObject)).next };
+        Some(object as *mut u8)
+    }
+
+    //push the object back onto the free list of its class
+    fn dealloc(&self, class: usize, ptr: *mut u8) {
+        let mut class_list = self.classes[class].lock();
+        unsafe { (*(ptr as *mut FreeObject)).next = class_list.free };
+        class_list.free = ptr as usize;
+    }
+
+    //map a fresh page (a frame from the frame allocator) and cut it into objects
+    //returns the first object, the objects are linked in address order
+    fn new_slab(&self, object_size: usize) -> Option<usize> {
+        // the virtual page is not given back if it can't be mapped, there are plenty
+        let page = self.next_page.fetch_add(PAGE_SIZE, Ordering::Relaxed);
+        if page + PAGE_SIZE > SLAB_START + SLAB_SIZE || !::memory::map_pages(page, page + PAGE_SIZE) {
+            return None;
+        }
+
+        let count = PAGE_SIZE / object_size;
+        for i in 0..count {
+            let object = page + i * object_size;
+            let next = if i + 1 < count { object + object_size } else { 0 };
+            unsafe { (*(object as *mut FreeObject)).next = next };
+        }
+        Some(page)
+    }
+}
+
+//global allocator that serves small layouts from the slab allocator and everything else from HEAP_ALLOCATOR
+//before the heap is ready, everything comes from the early heap
+pub struct KernelAllocator {
+    slab: SlabAllocator,
+    slab_enabled: AtomicBool,
+    heap_ready: AtomicBool,
+}
+
+impl KernelAllocator {
+    // with the heap-debug feature everything goes to the heap, so that every block gets red zones
+    pub const fn new() -> Self {
+        Self {
+            slab: SlabAllocator::new(),
+            slab_enabled: AtomicBool::new(!cfg!(feature = "heap-debug")),
+            heap_ready: AtomicBool::new(false),
+        }
+    }
+
+    /// Switches from the early heap to the slab allocator and the kernel heap.
+    /// Must be called after HEAP_ALLOCATOR was initialized.
+    pub fn set_heap_ready(&self) {
+        self.heap_ready.store(true, Ordering::Release);
+    }
+
+    /// Turns the slab allocator on or off. When it is off, all new allocations
+    /// go to the linked list heap; objects in slabs can still be freed.
+    pub fn set_slab_enabled(&self, enabled: bool) {
+        self.slab_enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn slab_enabled(&self) -> bool {
+        self.slab_enabled.load(Ordering::Relaxed)
+    }
+}
+
+impl KernelAllocator {
+
+    unsafe fn allocate(&self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        if !self.heap_ready.load(Ordering::Acquire) {
+            return ::memory::early_heap::alloc(layout);
+        }
+        if self.slab_enabled.load(Ordering::Relaxed) {
+            if let Some(class) = class_index(&layout) {
+                if let Some(ptr) = self.slab.alloc(class) {
+                    return Ok(ptr);
+                }
+                // no new slab could be mapped, try the heap
+            }
+        }
+        let mut heap = &::HEAP_ALLOCATOR;
+        heap.alloc(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        if ::memory::early_heap::contains(ptr) {
+            // blocks of the early heap are leaked once the real heap is used
+            if !self.heap_ready.load(Ordering::Acquire) {
+                ::memory::early_heap::dealloc(ptr, layout);
+            }
+            return;
+        }
+        // decide by address, the slab might have been turned off since the allocation
+        if self.slab.contains(ptr as usize) {
+            let class = class_index(&layout).expect("slab object freed with a too large layout");
+            self.slab.dealloc(class, ptr);
+        } else {
+            let mut heap = &::HEAP_ALLOCATOR;
+            heap.dealloc(ptr, layout)
+        }
+    }
+}
+
+unsafe impl<'a> Alloc for &'a KernelAllocator {
+
+    #[inline(always)]
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        #[cfg(feature = "heap-tracking")]
+        let size = layout.size();
+
+        // give the reclaim callbacks a chance before the allocation fails
+        let mut result = self.allocate(layout.clone());
+        while result.is_err() && ::memory::oom::reclaim() > 0 {
+            result = self.allocate(layout.clone());
+        }
+
+        #[cfg(feature = "heap-tracking")]
+        {
+            if let Ok(ptr) = result {
+                let caller = ::memory::heap_stats::caller_address();
+                ::memory::heap_stats::record(ptr as usize, size, caller);
+            }
+        }
+        result
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        #[cfg(feature = "heap-tracking")]
+        ::memory::heap_stats::forget(ptr as usize, layout.size());
+
+        self.deallocate(ptr, layout)
+    }
+
+    // called by the collections when an allocation failed
+    fn oom(&mut self, err: AllocErr) -> ! {
+        ::memory::oom::heap_exhausted(err)
+    }
+}
+
+/// Runs the `format!` loop of `rust_main` once with the slab allocator and
+/// once with the plain linked list heap and prints the cycles each run took.
+#[cfg(feature = "slab-benchmark")]
+pub fn benchmark(allocator: &KernelAllocator) {
+    use x86_64::instructions::rdtsc;
+
+    fn format_loop() -> u64 {
+        let start = rdtsc();
+        for _ in 0..10000 {
+            format!("Some String");
+        }
+        rdtsc() - start
+    }
+
+    let enabled = allocator.slab_enabled();
+    allocator.set_slab_enabled(false);
+    let heap_cycles = format_loop();
+    allocator.set_slab_enabled(true);
+    let slab_cycles = format_loop();
+    allocator.set_slab_enabled(enabled);
+
+    println!("format! loop: linked list heap {} cycles, slab {} cycles",
+             heap_cycles, slab_cycles);
+}
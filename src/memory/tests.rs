@@ -0,0 +1,123 @@
+// kernel tests of the memory module (cargo feature "kernel-test")
+
+use alloc::boxed::Box;
+use super::{FrameAllocator, PAGE_SIZE, MEMORY_CONTROLLER};
+use super::paging::{WRITABLE, NO_EXECUTE};
+use super::paging::kmap::kmap;
+
+kernel_test!(heap_box, {
+    let mut heap_test = Box::new(42);
+    *heap_test -= 15;
+    assert_eq!(*heap_test, 27);
+});
+
+kernel_test!(heap_vec, {
+    let mut vec_test = vec![1, 2, 3, 4, 5, 6, 7];
+    vec_test[3] = 42;
+    assert_eq!(vec_test.iter().sum::<i32>(), 66);
+});
+
+kernel_test!(heap_many_strings, {
+    for _ in 0..10000 {
+        assert_eq!(format!("Some String").len(), 11);
+    }
+});
+
+// copy between two frames that are mapped at the same time
+kernel_test!(kmap_two_frames, {
+    let (a, b) = {
+        let mut controller = MEMORY_CONTROLLER.lock();
+        let allocator = &mut controller.as_mut().unwrap().frame_allocator;
+        (allocator.allocate_frame().unwrap(), allocator.allocate_frame().unwrap())
+    };
+
+    let src = kmap(a);
+    let dst = kmap(b);
+    assert!(src.address() != dst.address());
+    unsafe {
+        let src = src.address() as *mut u64;
+        let dst = dst.address() as *mut u64;
+        for i in 0..(PAGE_SIZE / 8) as isize {
+            *src.offset(i) = i as u64;
+        }
+        for i in 0..(PAGE_SIZE / 8) as isize {
+            *dst.offset(i) = *src.offset(i);
+        }
+        assert_eq!(*dst.offset(511), 511);
+    }
+});
+
+kernel_test!(vmalloc_ranges_do_not_overlap, {
+    let a = super::vmalloc(3 * PAGE_SIZE, WRITABLE | NO_EXECUTE).unwrap();
+    let b = super::vmalloc(PAGE_SIZE, WRITABLE | NO_EXECUTE).unwrap();
+    assert!(b >= a + 3 * PAGE_SIZE);
+    unsafe {
+        *((a + 3 * PAGE_SIZE - 8) as *mut u64) = 42;
+        *(b as *mut u64) = 43;
+        assert_eq!(*((a + 3 * PAGE_SIZE - 8) as *mut u64), 42);
+    }
+    assert!(super::find_vma(a).is_some());
+
+    super::vfree(a);
+    super::vfree(b);
+    assert!(super::find_vma(a).is_none());
+});
+
+kernel_test!(vmalloc_zero_size, {
+    assert_eq!(super::vmalloc(0, WRITABLE | NO_EXECUTE), None);
+    assert_eq!(super::map_mmio(0, 0), None);
+});
+
+// vfree gives the frames of a vmalloc range back
+kernel_test!(vfree_returns_frames, {
+    use super::area_frame_allocator::frame_statistics;
+
+    // the first cycle may create page tables and grow the range lists
+    let a = super::vmalloc(4 * PAGE_SIZE, WRITABLE | NO_EXECUTE).unwrap();
+    super::vfree(a);
+
+    let (allocated, _) = frame_statistics();
+    let a = super::vmalloc(4 * PAGE_SIZE, WRITABLE | NO_EXECUTE).unwrap();
+    assert_eq!(frame_statistics().0, allocated + 4);
+    super::vfree(a);
+    assert_eq!(frame_statistics().0, allocated);
+});
+
+kernel_test!(vma_split_and_merge, {
+    use super::vma::{Vma, VmaSet, Backing};
+
+    let mut vmas = VmaSet::new();
+    vmas.insert(Vma::new(0x1000, 0x5000, WRITABLE, Backing::Physical(0x10000)));
+    assert!(vmas.split(0x3000));
+    assert_eq!(vmas.find(0x3000).unwrap().backing, Backing::Physical(0x12000));
+
+    vmas.merge();
+    assert_eq!(vmas.iter().count(), 1);
+
+    assert_eq!(vmas.remove_range(0x2000, 0x3000), 1);
+    assert!(vmas.find(0x2000).is_none());
+    assert_eq!(vmas.iter().count(), 2);
+});
+
+kernel_test!(arena_reset, {
+    use alloc::heap::{Alloc, Layout};
+    use super::heap_allocator::scratch_arena;
+
+    let arena = scratch_arena(PAGE_SIZE).unwrap();
+    let layout = Layout::from_size_align(96, 8).unwrap();
+    unsafe {
+        let mut allocator = &arena;
+        let a = allocator.alloc(layout.clone()).unwrap();
+        let b = allocator.alloc(layout.clone()).unwrap();
+        // the most recent block can be freed
+        allocator.dealloc(b, layout.clone());
+        assert_eq!(arena.used(), 96);
+        assert_eq!(arena.high_water_mark(), 192);
+
+        allocator.alloc(layout.clone()).unwrap();
+        arena.reset();
+        assert_eq!(arena.used(), 0);
+        assert_eq!(allocator.alloc(layout.clone()).unwrap(), a);
+    }
+    super::vfree(arena.start());
+});
@@ -0,0 +1,183 @@
+// virtual memory areas (VMAs)
+// records what a region of an address space is meant to be, page table entries only tell what is mapped right now
+// the page fault handler and mmap/munmap style functions look up the VMA of an address to decide what to do
+
+use alloc::Vec;
+use memory::PAGE_SIZE;
+use memory::paging::{VirtualAddress, PhysicalAddress, EntryFlags};
+
+// where the content of a region comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    // zeroed frames that are allocated on demand (heap, stacks)
+    Anonymous,
+    // a fixed physical range, the region starts at the given physical address (device memory)
+    Physical(PhysicalAddress),
+    // a file, the region starts at the given offset into the file
+    File { file: usize, offset: usize },
+}
+
+/// A page aligned region of an address space. `end` is exclusive.
+/// A region without the `PRESENT` flag is a guard region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vma {
+    pub start: VirtualAddress,
+    pub end: VirtualAddress,
+    pub flags: EntryFlags,
+    pub backing: Backing,
+}
+
+impl Vma {
+
+    pub fn new(start: VirtualAddress, end: VirtualAddress, flags: EntryFlags,
+        backing: Backing) -> Vma
+    {
+        assert!(start % PAGE_SIZE == 0 && end % PAGE_SIZE == 0,
+                "VMAs need to be page aligned");
+        assert!(start < end, "empty VMA");
+        Vma { start: start, end: end, flags: flags, backing: backing }
+    }
+
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        address >= self.start && address < self.end
+    }
+
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    // a region without the PRESENT flag must never be mapped
+    pub fn is_guard(&self) -> bool {
+        !self.flags.contains(::memory::paging::PRESENT)
+    }
+
+    // the backing of the part of the region that starts at `address`
+    fn backing_at(&self, address: VirtualAddress) -> Backing {
+        let offset = address - self.start;
+        match self.backing {
+            Backing::Anonymous => Backing::Anonymous,
+            Backing::Physical(phys) => Backing::Physical(phys + offset),
+            Backing::File { file, offset: file_offset } => {
+                Backing::File { file: file, offset: file_offset + offset }
+            }
+        }
+    }
+
+    // true if `next` directly follows this region and continues it
+    fn can_merge(&self, next: &Vma) -> bool {
+        self.end == next.start && self.flags == next.flags &&
+            self.backing_at(self.end) == next.backing
+    }
+}
+
+/// The VMAs of one address space, sorted by start address and never overlapping.
+pub struct VmaSet {
+    vmas: Vec<Vma>,
+}
+
+impl VmaSet {
+
+    pub fn new() -> VmaSet {
+        VmaSet { vmas: Vec::new() }
+    }
+
+    pub fn iter(&self) -> ::core::slice::Iter<Vma> {
+        self.vmas.iter()
+    }
+
+    /// The list of the VMAs, so that it can be replaced by a larger one while
+    /// a lock is held that the heap needs to grow. It has to stay sorted.
+    pub fn storage_mut(&mut self) -> &mut Vec<Vma> {
+        &mut self.vmas
+    }
+
+    /// Returns the VMA that contains the given address.
+    pub fn find(&self, address: VirtualAddress) -> Option<&Vma> {
+        self.vmas.iter().find(|vma| vma.contains(address))
+    }
+
+    /// Returns true if no VMA overlaps the range `start..end`.
+    pub fn is_free(&self, start: VirtualAddress, end: VirtualAddress) -> bool {
+        self.vmas.iter().all(|vma| vma.end <= start || vma.start >= end)
+    }
+
+    /// Adds a new VMA. Panics if it overlaps an existing one.
+    pub fn insert(&mut self, vma: Vma) {
+        assert!(self.is_free(vma.start, vma.end),
+                "VMA {:#x}..{:#x} overlaps an existing VMA", vma.start, vma.end);
+        let index = self.vmas.iter().position(|v| v.start > vma.start)
+            .unwrap_or(self.vmas.len());
+        self.vmas.insert(index, vma);
+    }
+
+    /// Splits the VMA that contains `address` into two VMAs at `address`.
+    /// Returns false if there is no VMA or it already starts at `address`.
+    pub fn split(&mut self, address: VirtualAddress) -> bool {
+        assert!(address % PAGE_SIZE == 0, "VMAs can only be split at page boundaries");
+
+        let index = match self.vmas.iter().position(|vma| vma.contains(address)) {
+            Some(index) => index,
+            None => return false,
+        };
+        if self.vmas[index].start == address {
+            return false;
+        }
+
+        let vma = self.vmas[index];
+        let upper = Vma {
+            start: address,
+            end: vma.end,
+            flags: vma.flags,
+            backing: vma.backing_at(address),
+        };
+        self.vmas[index].end = address;
+        self.vmas.insert(index + 1, upper);
+        true
+    }
+
+    /// Merges neighbouring VMAs with the same flags and continuous backing.
+    pub fn merge(&mut self) {
+        let mut i = 0;
+        while i + 1 < self.vmas.len() {
+            if self.vmas[i].can_merge(&self.vmas[i + 1]) {
+                let next = self.vmas.remove(i + 1);
+                self.vmas[i].end = next.end;
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Removes everything in `start..end` (munmap), VMAs that are only partly
+    /// inside the range are split first. Returns the number of removed VMAs.
+    /// Allocates nothing, it is called with the memory controller locked.
+    pub fn remove_range(&mut self, start: VirtualAddress, end: VirtualAddress) -> usize {
+        self.split(start);
+        self.split(end);
+
+        let mut removed = 0;
+        let mut i = 0;
+        while i < self.vmas.len() {
+            if self.vmas[i].start >= start && self.vmas[i].end <= end {
+                self.vmas.remove(i);
+                removed += 1;
+            } else {
+                i += 1;
+            }
+        }
+        removed
+    }
+
+    /// Changes the flags of everything in `start..end` (mprotect).
+    pub fn protect(&mut self, start: VirtualAddress, end: VirtualAddress, flags: EntryFlags) {
+        self.split(start);
+        self.split(end);
+
+        for vma in self.vmas.iter_mut() {
+            if vma.start >= start && vma.end <= end {
+                vma.flags = flags;
+            }
+        }
+        self.merge();
+    }
+}
@@ -0,0 +1,318 @@
+// kernel virtual address range allocator (vmalloc)
+// hands out page aligned ranges of a reserved kernel region so that new mappings don't need hand-picked addresses
+
+use core::mem;
+use alloc::Vec;
+use spin::MutexGuard;
+use memory::{PAGE_SIZE, Frame, FrameAllocator, MemoryController, MEMORY_CONTROLLER};
+use memory::paging::{Page, VirtualAddress, PhysicalAddress, EntryFlags};
+use memory::paging::{PRESENT, WRITABLE, NO_CACHE, WRITE_THROUGH, NO_EXECUTE};
+use memory::vma::{self, Vma, VmaSet};
+
+// the vmalloc region covers 1GiB starting at 8GiB
+pub const VMALLOC_START: usize = 0o_000_010_000_000_0000;
+pub const VMALLOC_SIZE: usize = 1024 * 1024 * 1024;
+// unmapped pages behind every range, so that overruns fault instead of corrupting the next range
+pub const DEFAULT_GUARD_PAGES: usize = 1;
+// free entries every list needs before a call changes it: a vmalloc adds an allocation and two
+// VMAs, a vfree adds a free range and splits at most two VMAs
+const LIST_ROOM: usize = 2;
+
+// a free range of pages, end is exclusive
+#[derive(Debug, Clone, Copy)]
+struct FreeRange {
+    start: VirtualAddress,
+    end: VirtualAddress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backing {
+    // frames were allocated by vmalloc and belong to the range
+    Frames,
+    // device memory mapped by map_mmio starting at the given frame address, the frames are not ours
+    Mmio(PhysicalAddress),
+}
+
+// a handed out range, the guard pages follow directly after the mapped pages
+#[derive(Debug, Clone, Copy)]
+struct Allocation {
+    start: VirtualAddress,
+    pages: usize,
+    guard_pages: usize,
+    backing: Backing,
+}
+
+impl Allocation {
+    fn mapped_end(&self) -> VirtualAddress {
+        self.start + self.pages * PAGE_SIZE
+    }
+
+    fn end(&self) -> VirtualAddress {
+        self.mapped_end() + self.guard_pages * PAGE_SIZE
+    }
+
+    // record the range and its guard pages in the VMAs of the kernel
+    fn insert_vmas(&self, vmas: &mut VmaSet, flags: EntryFlags) {
+        let backing = match self.backing {
+            Backing::Frames => vma::Backing::Anonymous,
+            Backing::Mmio(phys) => vma::Backing::Physical(phys),
+        };
+        vmas.insert(Vma::new(self.start, self.mapped_end(), flags | PRESENT, backing));
+        if self.guard_pages > 0 {
+            vmas.insert(Vma::new(self.mapped_end(), self.end(), EntryFlags::empty(),
+                vma::Backing::Anonymous));
+        }
+    }
+}
+
+/// Hands out non-overlapping page ranges of a virtual region. Free ranges are
+/// kept in a list sorted by address and merged again when ranges are freed.
+pub struct RangeAllocator {
+    start: VirtualAddress,
+    end: VirtualAddress,
+    guard_pages: usize,
+    free: Vec<FreeRange>,
+    allocations: Vec<Allocation>,
+}
+
+impl RangeAllocator {
+
+    pub fn new(start: VirtualAddress, end: VirtualAddress, guard_pages: usize)
+        -> RangeAllocator
+    {
+        RangeAllocator {
+            start: start,
+            end: end,
+            guard_pages: guard_pages,
+            free: Vec::new(),
+            allocations: Vec::new(),
+        }
+    }
+
+    /// Sets the number of unmapped pages that are left after every new range.
+    pub fn set_guard_pages(&mut self, guard_pages: usize) {
+        self.guard_pages = guard_pages;
+    }
+
+    // the free list is created on first use, the allocator is created before the heap is ready
+    fn free_list(&mut self) -> &mut Vec<FreeRange> {
+        if self.free.is_empty() && self.allocations.is_empty() {
+            let whole = FreeRange { start: self.start, end: self.end };
+            self.free.push(whole);
+        }
+        &mut self.free
+    }
+
+    // first fit search for `pages` pages plus the guard pages
+    fn reserve(&mut self, pages: usize, backing: Backing) -> Option<Allocation> {
+        let guard_pages = self.guard_pages;
+        let size = (pages + guard_pages) * PAGE_SIZE;
+
+        let index = match self.free_list().iter()
+            .position(|range| range.end - range.start >= size)
+        {
+            Some(index) => index,
+            None => return None,
+        };
+
+        // cut the range off the front of the free range
+        let start = self.free[index].start;
+        self.free[index].start += size;
+        if self.free[index].start == self.free[index].end {
+            self.free.remove(index);
+        }
+
+        let allocation = Allocation {
+            start: start,
+            pages: pages,
+            guard_pages: guard_pages,
+            backing: backing,
+        };
+        self.allocations.push(allocation);
+        Some(allocation)
+    }
+
+    // give the range starting at `start` back and merge it with its neighbours
+    fn release(&mut self, start: VirtualAddress) -> Option<Allocation> {
+        let index = match self.allocations.iter().position(|a| a.start == start) {
+            Some(index) => index,
+            None => return None,
+        };
+        let allocation = self.allocations.remove(index);
+        let mut range = FreeRange { start: allocation.start, end: allocation.end() };
+
+        // the list is sorted, find the place of the range
+        let index = self.free.iter().position(|r| r.start > range.start)
+            .unwrap_or(self.free.len());
+
+        // merge with the following range
+        if index < self.free.len() && self.free[index].start == range.end {
+            range.end = self.free.remove(index).end;
+        }
+        // merge with the previous range
+        if index > 0 && self.free[index - 1].end == range.start {
+            self.free[index - 1].end = range.end;
+        } else {
+            self.free.insert(index, range);
+        }
+        Some(allocation)
+    }
+}
+
+// the capacity `list` needs for LIST_ROOM more elements, 0 if it has the room
+fn needed_capacity<T>(list: &Vec<T>) -> usize {
+    if list.capacity() - list.len() >= LIST_ROOM {
+        0
+    } else {
+        (list.len() + LIST_ROOM) * 2
+    }
+}
+
+// moves the elements into `larger` if `list` still lacks room and `larger` has it
+fn grow_list<T: Copy>(list: &mut Vec<T>, larger: &mut Vec<T>) {
+    if list.capacity() - list.len() < LIST_ROOM && larger.capacity() >= list.len() + LIST_ROOM {
+        // no allocation, the capacity is large enough
+        larger.extend_from_slice(list);
+        mem::swap(list, larger);
+    }
+}
+
+// locks the memory controller with room in the range and VMA lists; the heap can't grow while
+// the controller is locked (map_pages needs it), so larger lists are allocated before
+fn lock_controller() -> MutexGuard<'static, Option<MemoryController>> {
+    loop {
+        let capacities = {
+            let mut guard = MEMORY_CONTROLLER.lock();
+            let capacities = {
+                let controller = guard.as_mut().expect("memory is not initialized");
+                [needed_capacity(&controller.vmalloc.free),
+                 needed_capacity(&controller.vmalloc.allocations),
+                 needed_capacity(controller.vmas.storage_mut())]
+            };
+            if capacities == [0, 0, 0] {
+                return guard;
+            }
+            capacities
+        };
+
+        let mut free = Vec::with_capacity(capacities[0]);
+        let mut allocations = Vec::with_capacity(capacities[1]);
+        let mut vmas = Vec::with_capacity(capacities[2]);
+        {
+            let mut guard = MEMORY_CONTROLLER.lock();
+            let controller = guard.as_mut().expect("memory is not initialized");
+            grow_list(&mut controller.vmalloc.free, &mut free);
+            grow_list(&mut controller.vmalloc.allocations, &mut allocations);
+            grow_list(controller.vmas.storage_mut(), &mut vmas);
+        }
+        // the old lists are freed here, after the controller is unlocked
+    }
+}
+
+/// Sets the number of guard pages left unmapped behind every new vmalloc range.
+pub fn set_guard_pages(guard_pages: usize) {
+    let mut controller = MEMORY_CONTROLLER.lock();
+    let controller = controller.as_mut().expect("memory is not initialized");
+    controller.vmalloc.set_guard_pages(guard_pages);
+}
+
+/// Reserves a virtual range of at least `size` bytes, backs it with fresh
+/// frames and maps it with the given flags. Returns the start address of the
+/// range or `None` if `size` is zero, no large enough range is left or the
+/// frames run out.
+pub fn vmalloc(size: usize, flags: EntryFlags) -> Option<VirtualAddress> {
+    if size == 0 {
+        return None;
+    }
+    let mut controller = lock_controller();
+    let controller = controller.as_mut().expect("memory is not initialized");
+
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let allocation = match controller.vmalloc.reserve(pages, Backing::Frames) {
+        Some(allocation) => allocation,
+        None => return None,
+    };
+    allocation.insert_vmas(&mut controller.vmas, flags);
+
+    for i in 0..pages {
+        let page = Page::containing_address(allocation.start + i * PAGE_SIZE);
+        let frame = match controller.frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            // out of frames, undo the pages mapped so far and give the range back
+            None => {
+                for j in 0..i {
+                    let page = Page::containing_address(allocation.start + j * PAGE_SIZE);
+                    controller.active_table.unmap(page, &mut controller.frame_allocator);
+                }
+                controller.vmas.remove_range(allocation.start, allocation.end());
+                controller.vmalloc.release(allocation.start);
+                return None;
+            }
+        };
+        controller.active_table.map_to(page, frame, flags, &mut controller.frame_allocator);
+    }
+    Some(allocation.start)
+}
+
+/// Unmaps a range returned by `vmalloc` or `map_mmio` and makes it available again.
+pub fn vfree(address: VirtualAddress) {
+    let mut controller = lock_controller();
+    let controller = controller.as_mut().expect("memory is not initialized");
+
+    let allocation = controller.vmalloc.release(address & !(PAGE_SIZE - 1))
+        .expect("vfree: address was not handed out by vmalloc");
+    controller.vmas.remove_range(allocation.start, allocation.end());
+
+    for i in 0..allocation.pages {
+        let page = Page::containing_address(allocation.start + i * PAGE_SIZE);
+        match allocation.backing {
+            Backing::Frames => controller.active_table.unmap(page, &mut controller.frame_allocator),
+            // the device frames are not ours
+            Backing::Mmio(_) => { controller.active_table.unmap_frame(page); }
+        }
+    }
+}
+
+/// Maps `len` bytes of device memory at the physical address `phys` into the
+/// vmalloc region. The mapping is uncached and not executable.
+/// Returns the virtual address that corresponds to `phys`.
+pub fn map_mmio(phys: PhysicalAddress, len: usize) -> Option<VirtualAddress> {
+    map_physical(phys, len, WRITABLE | NO_CACHE | WRITE_THROUGH | NO_EXECUTE)
+}
+
+/// Maps `len` bytes of memory at the physical address `phys` into the vmalloc
+/// region with the write-combining memory type, e.g. a framebuffer.
+/// Returns the virtual address that corresponds to `phys`.
+pub fn map_write_combining(phys: PhysicalAddress, len: usize) -> Option<VirtualAddress> {
+    map_physical(phys, len, WRITABLE | ::memory::pat::WRITE_COMBINING | NO_EXECUTE)
+}
+
+/// Maps `len` bytes at the physical address `phys` with the given flags.
+/// Returns `None` if `len` is zero.
+pub fn map_physical(phys: PhysicalAddress, len: usize, flags: EntryFlags)
+    -> Option<VirtualAddress>
+{
+    if len == 0 {
+        return None;
+    }
+    let mut controller = lock_controller();
+    let controller = controller.as_mut().expect("memory is not initialized");
+
+    let start_frame = Frame::containing_address(phys);
+    let end_frame = Frame::containing_address(phys + len - 1);
+    let pages = end_frame.number - start_frame.number + 1;
+    let start = match controller.vmalloc.reserve(pages, Backing::Mmio(start_frame.start_address())) {
+        Some(allocation) => {
+            allocation.insert_vmas(&mut controller.vmas, flags);
+            allocation.start
+        }
+        None => return None,
+    };
+
+    for (i, frame) in Frame::range_inclusive(start_frame, end_frame).enumerate() {
+        let page = Page::containing_address(start + i * PAGE_SIZE);
+        controller.active_table.map_to(page, frame, flags | PRESENT,
+            &mut controller.frame_allocator);
+    }
+    Some(start + phys % PAGE_SIZE)
+}
@@ -0,0 +1,36 @@
+// raw walker over the tags of the multiboot2 information structure
+// for the tags the multiboot2 crate doesn't parse (command line, framebuffer)
+//
+//   | total_size: u32 | reserved: u32 | tag | tag | ... | end tag |
+//
+// every tag starts with its type and size and is padded to 8 bytes
+
+pub const END: u32 = 0;
+pub const COMMAND_LINE: u32 = 1;
+pub const FRAMEBUFFER: u32 = 8;
+
+#[repr(C)]
+pub struct TagHeader {
+    pub tag_type: u32,
+    pub size: u32,
+}
+
+/// Returns the address of the first tag of the given type. The information
+/// structure has to be mapped.
+pub unsafe fn find_tag(multiboot_information_address: usize, tag_type: u32) -> Option<usize> {
+    let total_size = *(multiboot_information_address as *const u32) as usize;
+    let end = multiboot_information_address + total_size;
+
+    let mut tag = multiboot_information_address + 8;
+    while tag + 8 <= end {
+        let header = &*(tag as *const TagHeader);
+        if header.tag_type == END || header.size < 8 {
+            break;
+        }
+        if header.tag_type == tag_type {
+            return Some(tag);
+        }
+        tag += (header.size as usize + 7) & !7;
+    }
+    None
+}
@@ -0,0 +1,138 @@
+// panic screen
+// shows the message, the location, the registers and the memory usage in white on red, then
+// halts; the code that panicked may hold the console locks, so the text is written straight
+// into the VGA buffer (or the framebuffer, if its writer isn't locked) and to the serial port
+// a panic while the screen is drawn (e.g. in the statistics) only adds a line at the bottom
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::MutexGuard;
+use framebuffer::{FramebufferWriter, FRAMEBUFFER_CONSOLE};
+use serial::{SerialPort, COM1_BASE};
+use vga_buffer::{Color, PanicWriter};
+
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+// the registers in the panic handler, RIP points into it
+struct Registers {
+    rsp: u64,
+    rbp: u64,
+    rip: u64,
+    cr2: u64,
+    cr3: u64,
+    rflags: u64,
+}
+
+impl Registers {
+    // inlined, so that RSP and RBP belong to the frame of the caller
+    #[inline(always)]
+    fn capture() -> Registers {
+        let (rsp, rbp, rip, cr2, cr3, rflags): (u64, u64, u64, u64, u64, u64);
+        unsafe {
+            asm!("mov %rsp, $0" : "=r"(rsp) ::: "volatile");
+            asm!("mov %rbp, $0" : "=r"(rbp) ::: "volatile");
+            asm!("lea (%rip), $0" : "=r"(rip) ::: "volatile");
+            asm!("mov %cr2, $0" : "=r"(cr2) ::: "volatile");
+            asm!("mov %cr3, $0" : "=r"(cr3) ::: "volatile");
+            asm!("pushfq; pop $0" : "=r"(rflags) ::: "volatile");
+        }
+        Registers { rsp: rsp, rbp: rbp, rip: rip, cr2: cr2, cr3: cr3, rflags: rflags }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "    RSP {:#018x}  RBP {:#018x}  RIP    {:#018x}", self.rsp, self.rbp, self.rip)?;
+        writeln!(f, "    CR2 {:#018x}  CR3 {:#018x}  RFLAGS {:#018x}", self.cr2, self.cr3, self.rflags)
+    }
+}
+
+enum Screen {
+    Vga(PanicWriter),
+    Framebuffer(MutexGuard<'static, Option<FramebufferWriter>>),
+    // the framebuffer is locked, only the serial port is left
+    None,
+}
+
+/// Writes to the screen and to the serial port without taking a lock.
+pub struct PanicOutput {
+    screen: Screen,
+    serial: SerialPort,
+}
+
+impl fmt::Write for PanicOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = self.serial.write_str(s);
+        match self.screen {
+            Screen::Vga(ref mut writer) => writer.write_str(s)?,
+            Screen::Framebuffer(ref mut guard) => {
+                if let Some(ref mut writer) = **guard {
+                    writer.write_str(s);
+                }
+            }
+            Screen::None => {}
+        }
+        Ok(())
+    }
+}
+
+// clears the screen to red
+fn open_screen() -> Screen {
+    match FRAMEBUFFER_CONSOLE.try_lock() {
+        Some(mut guard) => {
+            if let Some(ref mut writer) = *guard {
+                writer.set_foreground(Color::White);
+                writer.set_background(Color::Red);
+                writer.clear();
+            }
+            if guard.is_some() {
+                Screen::Framebuffer(guard)
+            } else {
+                Screen::Vga(PanicWriter::new())
+            }
+        }
+        None => Screen::None,
+    }
+}
+
+// the screen of the first panic stays, the line goes below it
+fn nested_screen() -> Screen {
+    match FRAMEBUFFER_CONSOLE.try_lock() {
+        Some(ref guard) if guard.is_none() => Screen::Vga(PanicWriter::last_line()),
+        // the first panic still holds the framebuffer writer
+        _ => Screen::None,
+    }
+}
+
+/// The output of the panic screen for fatal errors that halt without a
+/// panic (out of memory). Clears the screen, a panic while writing to it
+/// only adds a line.
+pub fn fatal_output() -> PanicOutput {
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        return PanicOutput { screen: nested_screen(), serial: SerialPort::new(COM1_BASE) };
+    }
+    PanicOutput { screen: open_screen(), serial: SerialPort::new(COM1_BASE) }
+}
+
+/// Shows the panic screen and halts, called by the panic handler.
+pub fn show(fmt: fmt::Arguments, file: &'static str, line: u32) -> ! {
+    // before cli, RFLAGS shows whether interrupts were enabled at the panic
+    let registers = Registers::capture();
+    unsafe { asm!("cli" :::: "volatile") };
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        let mut output = PanicOutput { screen: nested_screen(), serial: SerialPort::new(COM1_BASE) };
+        let _ = output.serial.write_str("\n");
+        let _ = write!(output, "nested panic in {} at line {}: {}", file, line, fmt);
+        ::hlt_loop()
+    }
+
+    ::dmesg::store(::log::Level::Error, "panic", format_args!("{} at line {}: {}", file, line, fmt));
+
+    let mut output = PanicOutput { screen: open_screen(), serial: SerialPort::new(COM1_BASE) };
+    let _ = write!(output, "\nKERNEL PANIC in {} at line {}:\n    {}\n\n", file, line, fmt);
+    let _ = write!(output, "{}\n", registers);
+    let _ = write!(output, "{}\n", ::memory::oom::Statistics);
+    let _ = write!(output, "the system is halted");
+    ::hlt_loop()
+}
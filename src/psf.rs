@@ -0,0 +1,77 @@
+// parser for PC Screen Font (PSF) bitmap fonts, the format of the Linux console fonts
+// version 1 fonts are 8 pixels wide with 256 or 512 glyphs, version 2 fonts can have
+// any size; every glyph is stored row by row, each row padded to whole bytes
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+#[derive(Clone, Copy)]
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+fn read_u32(data: &[u8], offset: usize) -> usize {
+    (data[offset] as usize) | (data[offset + 1] as usize) << 8 |
+        (data[offset + 2] as usize) << 16 | (data[offset + 3] as usize) << 24
+}
+
+impl<'a> Font<'a> {
+    /// Parses a PSF1 or PSF2 font, returns `None` if the data is not a
+    /// valid font. The unicode table is ignored.
+    pub fn parse(data: &'a [u8]) -> Option<Font<'a>> {
+        let font = if data.len() >= 4 && data[..2] == PSF1_MAGIC {
+            let glyph_count = if data[2] & PSF1_MODE_512 != 0 { 512 } else { 256 };
+            let height = data[3] as usize;
+            Font {
+                glyphs: &data[4..],
+                glyph_count: glyph_count,
+                bytes_per_glyph: height,
+                width: 8,
+                height: height,
+            }
+        } else if data.len() >= 32 && data[..4] == PSF2_MAGIC {
+            let header_size = read_u32(data, 8);
+            if header_size > data.len() {
+                return None;
+            }
+            Font {
+                glyphs: &data[header_size..],
+                glyph_count: read_u32(data, 16),
+                bytes_per_glyph: read_u32(data, 20),
+                height: read_u32(data, 24),
+                width: read_u32(data, 28),
+            }
+        } else {
+            return None;
+        };
+
+        if font.glyphs.len() < font.glyph_count * font.bytes_per_glyph ||
+            font.bytes_per_glyph < font.bytes_per_row() * font.height {
+            return None;
+        }
+        Some(font)
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        (self.width + 7) / 8
+    }
+
+    /// The bitmap of the glyph, the first glyph if there is no such glyph.
+    /// Row `y` starts at `y * bytes_per_row()`, the most significant bit is
+    /// the leftmost pixel.
+    pub fn glyph(&self, index: usize) -> &'a [u8] {
+        let index = if index < self.glyph_count { index } else { 0 };
+        let start = index * self.bytes_per_glyph;
+        &self.glyphs[start..start + self.bytes_per_glyph]
+    }
+
+    /// True if pixel (x, y) of the glyph is set.
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        glyph[y * self.bytes_per_row() + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
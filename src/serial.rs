@@ -0,0 +1,118 @@
+// driver for the 16550 UART of the first serial port (COM1)
+// unlike the VGA buffer, its output can be read from outside when QEMU runs headless (-serial stdio)
+
+use core::fmt;
+use spin::Mutex;
+use x86_64::instructions::port::{inb, outb};
+
+pub const COM1_BASE: u16 = 0x3f8;
+pub const DEFAULT_BAUD_RATE: u32 = 115200;
+
+// the UART divides this clock by the divisor latch to get the baud rate
+const UART_CLOCK: u32 = 115200;
+
+// register offsets from the base port
+const DATA: u16 = 0;                // data / divisor latch low byte (DLAB = 1)
+const INTERRUPT_ENABLE: u16 = 1;    // interrupt enable / divisor latch high byte (DLAB = 1)
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+
+// line status bits
+const DATA_READY: u8 = 1 << 0;
+const TRANSMIT_EMPTY: u8 = 1 << 5;
+
+pub static COM1: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1_BASE));
+
+pub struct SerialPort {
+    base: u16,
+}
+
+impl SerialPort {
+    pub const fn new(base: u16) -> SerialPort {
+        SerialPort { base: base }
+    }
+
+    /// Sets the baud rate, 8 data bits, no parity, one stop bit and enables
+    /// the FIFOs. Interrupts stay disabled, the port is polled.
+    pub fn init(&mut self, baud_rate: u32) {
+        let divisor = (UART_CLOCK / baud_rate) as u16;
+        unsafe {
+            outb(self.base + INTERRUPT_ENABLE, 0x00);
+            // set DLAB to access the divisor latch
+            outb(self.base + LINE_CONTROL, 0x80);
+            outb(self.base + DATA, divisor as u8);
+            outb(self.base + INTERRUPT_ENABLE, (divisor >> 8) as u8);
+            // 8 bits, no parity, one stop bit, DLAB cleared
+            outb(self.base + LINE_CONTROL, 0x03);
+            // enable and clear the FIFOs, interrupt threshold 14 bytes
+            outb(self.base + FIFO_CONTROL, 0xc7);
+            // DTR, RTS and OUT2
+            outb(self.base + MODEM_CONTROL, 0x0b);
+        }
+    }
+
+    fn line_status(&self) -> u8 {
+        unsafe { inb(self.base + LINE_STATUS) }
+    }
+
+    //wait until the transmitter holding register is empty, then send the byte
+    pub fn write_byte(&mut self, byte: u8) {
+        while self.line_status() & TRANSMIT_EMPTY == 0 {}
+        unsafe { outb(self.base + DATA, byte) };
+    }
+
+    /// Returns the next received byte, if there is one.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if self.line_status() & DATA_READY != 0 {
+            Some(unsafe { inb(self.base + DATA) })
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            // terminals expect \r\n
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte)
+        }
+        Ok(())
+    }
+}
+
+/// Initializes COM1 with the default baud rate.
+pub fn init() {
+    COM1.lock().init(DEFAULT_BAUD_RATE);
+}
+
+/// Turns mirroring of the print!/println! output to the serial port on or off.
+pub fn set_mirror(enabled: bool) {
+    ::console::set_enabled("serial", enabled);
+}
+
+pub fn mirror_enabled() -> bool {
+    ::console::is_enabled("serial")
+}
+
+//Prints to the serial port, works like print! in vga_buffer.rs
+macro_rules! serial_print {
+    ($($arg:tt)*) => ({
+        $crate::serial::print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! serial_println {
+    ($fmt:expr) => (serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+pub fn print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    COM1.lock().write_fmt(args).unwrap();
+}
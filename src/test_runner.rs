@@ -0,0 +1,85 @@
+// in-kernel test runner (cargo feature "kernel-test", run with `make test`)
+// tests are registered with the kernel_test! macro, which puts a TestCase into the .kernel_tests
+// section; the linker collects them between __kernel_tests_start and __kernel_tests_end
+// results are reported over the serial port and QEMU is left through the isa-debug-exit device
+
+use core::fmt;
+
+pub struct TestCase {
+    pub name: &'static str,
+    pub func: fn(),
+}
+
+/// Registers a test that runs after memory::init when the kernel is built
+/// with the kernel-test feature.
+///
+/// ```ignore
+/// kernel_test!(box_on_heap, {
+///     let x = Box::new(41);
+///     assert_eq!(*x + 1, 42);
+/// });
+/// ```
+macro_rules! kernel_test {
+    ($name:ident, $body:block) => {
+        #[allow(non_upper_case_globals)]
+        #[link_section = ".kernel_tests"]
+        #[used]
+        static $name: $crate::test_runner::TestCase = $crate::test_runner::TestCase {
+            name: concat!(module_path!(), "::", stringify!($name)),
+            func: { fn test() $body test },
+        };
+    };
+}
+
+// exit codes for the isa-debug-exit device, QEMU exits with (code << 1) | 1
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+// the device has to be enabled with -device isa-debug-exit,iobase=0xf4,iosize=0x04
+pub fn exit_qemu(exit_code: QemuExitCode) -> ! {
+    use x86_64::instructions::port::outl;
+
+    unsafe { outl(0xf4, exit_code as u32) };
+    // only reached when the device is missing
+    ::hlt_loop()
+}
+
+// defined in linker.ld
+extern {
+    static __kernel_tests_start: u8;
+    static __kernel_tests_end: u8;
+}
+
+fn tests() -> &'static [TestCase] {
+    use core::{mem, slice};
+
+    unsafe {
+        let start = &__kernel_tests_start as *const u8 as usize;
+        let end = &__kernel_tests_end as *const u8 as usize;
+        let count = (end - start) / mem::size_of::<TestCase>();
+        slice::from_raw_parts(start as *const TestCase, count)
+    }
+}
+
+/// Runs all registered tests and exits QEMU. A failing test panics, the panic
+/// handler calls `test_panicked`.
+pub fn run_tests() -> ! {
+    let tests = tests();
+    serial_println!("running {} tests", tests.len());
+    for test in tests {
+        serial_print!("{} ... ", test.name);
+        (test.func)();
+        serial_println!("[ok]");
+    }
+    serial_println!("all tests passed");
+    exit_qemu(QemuExitCode::Success)
+}
+
+/// Reports the panic of the running test and exits QEMU with the failure code.
+pub fn test_panicked(fmt: fmt::Arguments, file: &'static str, line: u32) -> ! {
+    serial_println!("[failed]\n\npanicked in {} at line {}:\n    {}", file, line, fmt);
+    exit_qemu(QemuExitCode::Failed)
+}
@@ -1,11 +1,26 @@
 use spin::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use ansi::{self, Action, EscapeParser, Rendition, Terminal};
 
-//Provide a global writer that can used as an interface from other modules
-pub static WRITER: Mutex<Writer> = Mutex::new(Writer {
-    column_position: 0,
-    color_code: ColorCode::new(Color::Pink, Color::Black),
-    buffer: unsafe { Unique::new_unchecked(0xb8000 as *mut _) },
-});
+pub const CONSOLE_COUNT: usize = 4;
+
+//Provide virtual consoles that can used as an interface from other modules
+//every console writes to its own off-screen buffer, only the active one is mirrored to the VGA buffer
+//console 0 shows the kernel output (print!, the log) and is active at boot
+pub static CONSOLES: [Mutex<Writer>; CONSOLE_COUNT] = [
+    Mutex::new(Writer::new(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }, true)),
+    Mutex::new(Writer::new(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }, false)),
+    Mutex::new(Writer::new(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }, false)),
+    Mutex::new(Writer::new(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }, false)),
+];
+
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(0);
+
+//colors after reset (ESC[0m)
+const DEFAULT_FOREGROUND: Color = Color::Pink;
+const DEFAULT_BACKGROUND: Color = Color::Black;
+const DEFAULT_COLOR: ColorCode = ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND);
+const BLANK: ScreenChar = ScreenChar { ascii_character: b' ', color_code: DEFAULT_COLOR };
 
 #[allow(dead_code)]         //Normally the compiler would issue a warning for each unused variant.
                             //By using the #[allow(dead_code)] attribute we disable these warnings for the Color enum.
@@ -53,27 +68,113 @@ struct ScreenChar {
     color_code: ColorCode,
 }
 
-//Define buffer size
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+//Define buffer size, the largest text mode is 90x60 (see vga_mode.rs)
+//the writers start with the 80x25 of the BIOS and are resized by init and set_mode
+const MAX_HEIGHT: usize = 60;
+const MAX_WIDTH: usize = 90;
+const DEFAULT_HEIGHT: usize = 25;
+const DEFAULT_WIDTH: usize = 80;
+const TAB_WIDTH: usize = 8;
+
+//The hardware cursor is set through the CRT controller: write the register index to 0x3D4, then the value to 0x3D5
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CURSOR_START: u8 = 0x0a;
+const CURSOR_END: u8 = 0x0b;
+const CURSOR_LOCATION_HIGH: u8 = 0x0e;
+const CURSOR_LOCATION_LOW: u8 = 0x0f;
+//bit 5 of the cursor start register hides the cursor
+const CURSOR_DISABLE: u8 = 1 << 5;
+
+fn crtc_write(index: u8, value: u8) {
+    use x86_64::instructions::port::outb;
+    unsafe {
+        outb(CRTC_INDEX, index);
+        outb(CRTC_DATA, value);
+    }
+}
+
+fn crtc_read(index: u8) -> u8 {
+    use x86_64::instructions::port::{inb, outb};
+    unsafe {
+        outb(CRTC_INDEX, index);
+        inb(CRTC_DATA)
+    }
+}
 
 use volatile::Volatile;
 
 struct Buffer {
-    //Array of ScreenChars
+    //Array of ScreenChars, row by row with as many characters per row as the mode has columns
     //Volatile tells the compiler that the write has side effects and should not be optimized away.
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    chars: [Volatile<ScreenChar>; MAX_WIDTH * MAX_HEIGHT],
 }
 
 use core::ptr::Unique;
+use alloc::vec::Vec;
+use vga_mode::TextMode;
+
+//number of lines kept after they scrolled off the screen
+const SCROLLBACK_LINES: usize = 500;
+
+type Line = [ScreenChar; MAX_WIDTH];
+
+//history of the lines that left the screen at the top, a ring of SCROLLBACK_LINES lines
+//the vectors are allocated in full up front: growing them could print (heap debug output)
+//while the writer is locked
+struct Scrollback {
+    lines: Vec<Line>,
+    //index of the oldest line once the ring is full
+    start: usize,
+    //number of lines the view is scrolled up, 0 shows the live screen
+    offset: usize,
+}
+
+impl Scrollback {
+    fn push(&mut self, line: Line) {
+        if self.lines.len() < SCROLLBACK_LINES {
+            self.lines.push(line);
+        } else {
+            self.lines[self.start] = line;
+            self.start = (self.start + 1) % SCROLLBACK_LINES;
+        }
+    }
+
+    //the i-th line of the history, oldest first
+    fn line(&self, i: usize) -> &Line {
+        &self.lines[(self.start + i) % self.lines.len()]
+    }
+}
 
 //The writer will always write to the last line and shift lines up when a line is full (or on \n).
 pub struct Writer {
-    //keeps track of the current position in the last row.
+    //keeps track of the current position in the row.
     column_position: usize,
 
+    //the row we write to, the last one unless the cursor was moved with an escape sequence
+    row_position: usize,
+
     //specifies current foreground and background colors
-    color_code: ColorCode,
+    rendition: Rendition,
+
+    //position stored by ESC[s or ESC7 (row, column)
+    saved_position: (usize, usize),
+
+    //state of the escape sequence currently being written
+    parser: EscapeParser,
+
+    //lines that scrolled off the top, None until the heap is ready
+    scrollback: Option<Scrollback>,
+
+    //the off-screen buffer of the console, all output goes here
+    shadow: [Line; MAX_HEIGHT],
+
+    //size of the text mode, only this part of the buffers is used
+    width: usize,
+    height: usize,
+
+    //the active console also writes to the VGA buffer and moves the hardware cursor
+    active: bool,
 
     //Stores a pointer to the VGA buffer
     //Unique makes it possible to create a static Writer later
@@ -81,43 +182,203 @@ pub struct Writer {
 }
 
 impl Writer {
-    //method to write a single ASCII byte
+    const fn new(buffer: Unique<Buffer>, active: bool) -> Writer {
+        Writer {
+            column_position: 0,
+            row_position: DEFAULT_HEIGHT - 1,
+            rendition: Rendition::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+            saved_position: (DEFAULT_HEIGHT - 1, 0),
+            parser: EscapeParser::new(),
+            scrollback: None,
+            shadow: [[BLANK; MAX_WIDTH]; MAX_HEIGHT],
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            active: active,
+            buffer: buffer,
+        }
+    }
+
+    //method to write a single ASCII byte, the hardware cursor follows
     pub fn write_byte(&mut self, byte: u8) {
+        self.snap_to_bottom();
+        self.put_byte(byte);
+        self.update_cursor();
+    }
+
+    fn put_byte(&mut self, byte: u8) {
+        match self.parser.advance(byte) {
+            Some(Action::Print(byte)) => self.put_char(byte),
+            Some(action) => ansi::execute(self, action),
+            None => {}
+        }
+    }
+
+    fn put_char(&mut self, byte: u8) {
         match byte {
             //If the byte is the newline byte \n, the writer does not print anything. Instead it calls a new_line method
             b'\n' => self.new_line(),
-            //In this match case, Other bytes get printed to the screen.
-            byte => {
-                //When printing a byte, the writer checks if the current line is full. In that case, a new_line call is required before to wrap the line.
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
+            //carriage return, back to the start of the line
+            b'\r' => self.column_position = 0,
+            //tab, fill with spaces up to the next tab stop (every 8 columns)
+            b'\t' => {
+                self.put_char(b' ');
+                while self.column_position % TAB_WIDTH != 0 && self.column_position < self.width {
+                    self.put_char(b' ');
                 }
+            }
+            //backspace only moves the cursor, the next character overwrites the old one
+            0x08 => {
+                if self.column_position > 0 {
+                    self.column_position -= 1;
+                }
+            }
+            //bell, there is no speaker driver, so it is ignored instead of printed as a glyph
+            0x07 => {}
+            //In this match case, Other bytes get printed to the screen.
+            byte => self.put_glyph(byte),
+        }
+    }
 
-                //Row starts at 0, which is why we need to subtract 1
-                let row = BUFFER_HEIGHT - 1;
+    //writes the code page 437 glyph without interpreting it
+    fn put_glyph(&mut self, byte: u8) {
+        //When printing a byte, the writer checks if the current line is full. In that case, a new_line call is required before to wrap the line.
+        if self.column_position >= self.width {
+            self.new_line();
+        }
+
+        //the current row, the last one by default
+        let row = self.row_position;
 
-                //Te column is the current position
-                let col = self.column_position;
+        //Te column is the current position
+        let col = self.column_position;
 
-                let color_code = self.color_code;
+        let color_code = self.color_code();
 
-                //write a new ScreenChar to the buffer at the current position.
-                //Instead of a normal assignment using =, we're now using the write method. This guarantees that the compiler will never optimize away this write.
-                self.buffer().chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code: color_code,
-                });
+        //write a new ScreenChar to the buffer at the current position.
+        self.write_char(row, col, ScreenChar {
+            ascii_character: byte,
+            color_code: color_code,
+        });
 
-                //increment current column position
-                self.column_position += 1;
+        //increment current column position
+        self.column_position += 1;
+    }
+
+    pub fn write_str(&mut self, s: &str) {
+        //new output shows the live screen again
+        self.snap_to_bottom();
+
+        //ASCII goes through the escape sequence parser, other characters are shown as their
+        //code page 437 glyph (0xFE if there is none)
+        for c in s.chars() {
+            if (c as u32) < 0x80 {
+                self.put_byte(c as u8);
+            } else if self.parser.in_ground_state() {
+                self.put_glyph(::cp437::glyph(c));
+            } else {
+                //not allowed in an escape sequence, drop the sequence
+                self.parser.abort();
             }
         }
+        self.update_cursor();
     }
 
-    pub fn write_str(&mut self, s: &str) {
-        //Loop through all bytes in the string and print them
-        for byte in s.bytes() {
-          self.write_byte(byte)
+    //move the blinking hardware cursor to the current position
+    fn update_cursor(&mut self) {
+        //the cursor belongs to the active console
+        if !self.active {
+            return;
+        }
+        //at the end of a full line the cursor stays in the last column
+        let col = if self.column_position < self.width { self.column_position } else { self.width - 1 };
+        let position = self.row_position * self.width + col;
+        crtc_write(CURSOR_LOCATION_LOW, position as u8);
+        crtc_write(CURSOR_LOCATION_HIGH, (position >> 8) as u8);
+    }
+
+    pub fn show_cursor(&mut self) {
+        let start = crtc_read(CURSOR_START);
+        crtc_write(CURSOR_START, start & !CURSOR_DISABLE);
+    }
+
+    pub fn hide_cursor(&mut self) {
+        let start = crtc_read(CURSOR_START);
+        crtc_write(CURSOR_START, start | CURSOR_DISABLE);
+    }
+
+    /// Sets the scanlines (0 to 15) the cursor covers, e.g. 14 to 15 for an
+    /// underline or 0 to 15 for a block. Doesn't change the visibility.
+    pub fn set_cursor_shape(&mut self, start_scanline: u8, end_scanline: u8) {
+        let disabled = crtc_read(CURSOR_START) & CURSOR_DISABLE;
+        crtc_write(CURSOR_START, disabled | (start_scanline & 0x1f));
+        let end = crtc_read(CURSOR_END);
+        crtc_write(CURSOR_END, (end & 0xe0) | (end_scanline & 0x1f));
+    }
+
+    //write the string in another foreground color, the background stays
+    pub fn write_str_colored(&mut self, s: &str, foreground: Color) {
+        let old_foreground = self.rendition.foreground;
+        self.rendition.foreground = foreground as u8;
+        self.write_str(s);
+        self.rendition.foreground = old_foreground;
+    }
+
+    //writes the character to the off-screen buffer and, on the active console, to the VGA buffer
+    fn write_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.shadow[row][col] = character;
+        if self.active {
+            //Instead of a normal assignment using =, we're now using the write method. This guarantees that the compiler will never optimize away this write.
+            let width = self.width;
+            self.buffer().chars[row * width + col].write(character);
+        }
+    }
+
+    //makes this the console that is shown, or hides it
+    fn set_active(&mut self, active: bool) {
+        self.active = active;
+        if active {
+            self.redraw();
+            self.update_cursor();
+        }
+    }
+
+    //adapts the console to a new text mode, the newest lines stay on the screen
+    fn resize(&mut self, width: usize, height: usize) {
+        assert!(width <= MAX_WIDTH && height <= MAX_HEIGHT, "text mode {}x{} is too large", width, height);
+
+        //the lines that don't fit anymore go to the history
+        let surplus = (self.row_position + 1).saturating_sub(height);
+        for row in 0..surplus {
+            let line = self.shadow[row];
+            if let Some(ref mut scrollback) = self.scrollback {
+                scrollback.push(line);
+            }
+        }
+        for row in 0..MAX_HEIGHT {
+            self.shadow[row] = if row < height && row + surplus < self.height {
+                self.shadow[row + surplus]
+            } else {
+                [BLANK; MAX_WIDTH]
+            };
+            //the columns right of a narrower mode would show up again in a wider one
+            for col in width..MAX_WIDTH {
+                self.shadow[row][col] = BLANK;
+            }
+        }
+        if let Some(ref mut scrollback) = self.scrollback {
+            scrollback.offset = 0;
+        }
+
+        self.row_position -= surplus;
+        self.column_position = ::core::cmp::min(self.column_position, width);
+        let (saved_row, saved_col) = self.saved_position;
+        self.saved_position = (::core::cmp::min(saved_row, height - 1), ::core::cmp::min(saved_col, width - 1));
+        self.width = width;
+        self.height = height;
+
+        if self.active {
+            self.redraw();
+            self.update_cursor();
         }
     }
 
@@ -128,32 +389,147 @@ impl Writer {
     }
 
     fn new_line(&mut self) {
+        //we are at the 0th position in the next row
+        self.column_position = 0;
+
+        //above the last row there is no need to scroll
+        if self.row_position < self.height - 1 {
+            self.row_position += 1;
+            return;
+        }
+
+        //keep the 0th row in the history
+        let first_row = self.shadow[0];
+        if let Some(ref mut scrollback) = self.scrollback {
+            scrollback.push(first_row);
+        }
+
         //Loop through buffer
         //we start the row at 1 as the 0th row is shifted off screen
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let buffer = self.buffer();
-                let character = buffer.chars[row][col].read();
+        for row in 1..self.height {
+            //Move each line one row up
+            self.shadow[row - 1] = self.shadow[row];
+        }
+        let blank = ScreenChar { ascii_character: b' ', color_code: self.color_code() };
+        let last_row = self.height - 1;
+        self.shadow[last_row] = [blank; MAX_WIDTH];
+
+        if self.active {
+            self.redraw();
+        }
+    }
 
-                //Move each character one row up
-                buffer.chars[row - 1][col].write(character);
+    fn draw_row(&mut self, row: usize, line: &Line) {
+        let width = self.width;
+        for col in 0..width {
+            self.buffer().chars[row * width + col].write(line[col]);
+        }
+    }
+
+    //copies the view (the live screen or the history) to the VGA buffer
+    fn redraw(&mut self) {
+        let scrollback = self.scrollback.take();
+        let (history, offset) = scrollback.as_ref()
+            .map_or((0, 0), |scrollback| (scrollback.lines.len(), scrollback.offset));
+        for row in 0..self.height {
+            //index into the history followed by the live screen
+            let index = history - offset + row;
+            if index < history {
+                self.draw_row(row, scrollback.as_ref().unwrap().line(index));
+            } else {
+                let line = self.shadow[index - history];
+                self.draw_row(row, &line);
             }
         }
-        self.clear_row(BUFFER_HEIGHT-1);
+        self.scrollback = scrollback;
+    }
 
-        //we are at the 0th position in the next row
-        self.column_position = 0;
+    /// Shows `lines` older lines of the history.
+    pub fn scroll_up(&mut self, lines: usize) {
+        let offset = match self.scrollback {
+            Some(ref scrollback) => ::core::cmp::min(scrollback.offset + lines, scrollback.lines.len()),
+            None => return,
+        };
+        self.set_view(offset);
+    }
+
+    /// Shows `lines` newer lines, down to the live screen.
+    pub fn scroll_down(&mut self, lines: usize) {
+        let offset = match self.scrollback {
+            Some(ref scrollback) => scrollback.offset.saturating_sub(lines),
+            None => return,
+        };
+        self.set_view(offset);
+    }
+
+    fn snap_to_bottom(&mut self) {
+        if self.scrollback.as_ref().map_or(false, |scrollback| scrollback.offset > 0) {
+            self.set_view(0);
+        }
+    }
+
+    //shows the screen `offset` lines above the live screen, the off-screen buffer stays as it is
+    fn set_view(&mut self, offset: usize) {
+        let changed = match self.scrollback {
+            Some(ref mut scrollback) => {
+                let changed = scrollback.offset != offset;
+                scrollback.offset = offset;
+                changed
+            }
+            None => false,
+        };
+        if changed && self.active {
+            self.redraw();
+        }
+    }
+
+    /// The character and the color code at the position of the off-screen
+    /// buffer, for the kernel tests.
+    #[cfg(feature = "kernel-test")]
+    pub fn cell(&self, row: usize, col: usize) -> (u8, u8) {
+        let character = self.shadow[row][col];
+        (character.ascii_character, character.color_code.0)
+    }
+
+    //the colors set by the escape sequences
+    fn color_code(&self) -> ColorCode {
+        ColorCode(self.rendition.background << 4 | self.rendition.foreground)
+    }
+}
+
+//the escape sequences of ansi.rs act on the console
+impl Terminal for Writer {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    fn cursor(&self) -> (usize, usize) {
+        (self.row_position, self.column_position)
     }
-    //This method clears a row by overwriting all of its characters with a space character.
-    fn clear_row(&mut self, row: usize) {
+
+    fn set_cursor(&mut self, row: usize, column: usize) {
+        self.row_position = row;
+        self.column_position = column;
+    }
+
+    fn saved_cursor(&mut self) -> &mut (usize, usize) {
+        &mut self.saved_position
+    }
+
+    fn rendition(&mut self) -> &mut Rendition {
+        &mut self.rendition
+    }
+
+    //clears the columns start..end of the row
+    fn clear_columns(&mut self, row: usize, start: usize, end: usize) {
         //Blank is a space character
         let blank = ScreenChar {
             ascii_character: b' ',
-            color_code: self.color_code,
+            color_code: self.color_code(),
         };
-        //Loop through each position in the row and overwrite the characters with blank
-        for col in 0..BUFFER_WIDTH {
-            self.buffer().chars[row][col].write(blank);
+        //Loop through each position and overwrite the characters with blank
+        for col in start..end {
+            self.write_char(row, col, blank);
         }
     }
 }
@@ -165,21 +541,14 @@ use core::fmt;
 //To implement the trait, we just need to move it into an impl fmt::Write for Writer block and add a return type:
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        for byte in s.bytes() {
-          self.write_byte(byte)
-        }
+        Writer::write_str(self, s);
         Ok(())
     }
 }
 
 pub fn print_something() {
     use core::fmt::Write;
-    let mut writer = Writer {
-        //Start writing at the beginning of the buffer
-        column_position: 0,
-        color_code: ColorCode::new(Color::Pink, Color::Black),
-        buffer: unsafe { Unique::new_unchecked(0xb8000 as *mut _) },
-    };
+    let mut writer = Writer::new(unsafe { Unique::new_unchecked(0xb8000 as *mut _) }, true);
 
     writer.write_byte(b'H');
     writer.write_str("ello! ");
@@ -207,9 +576,9 @@ macro_rules! print {
     });
 }
 
+//The message goes to every enabled console (VGA, serial, ring buffer...), see console.rs
 pub fn print(args: fmt::Arguments) {
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    ::console::write_fmt(args);
 }
 
 macro_rules! println {
@@ -228,8 +597,179 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::io::_print(format_args!($($arg)*)));
 }*/
 
+/// Keeps the last 500 lines that scrolled off the screen for scroll_up and
+/// scroll_down. Call it once the heap is ready.
+pub fn enable_scrollback() {
+    for console in CONSOLES.iter() {
+        //allocate before locking, the allocator may print
+        let scrollback = Scrollback {
+            lines: Vec::with_capacity(SCROLLBACK_LINES),
+            start: 0,
+            offset: 0,
+        };
+        console.lock().scrollback = Some(scrollback);
+    }
+}
+
+/// Switches the VGA card to another text mode and resizes all consoles to
+/// it. Only for text mode, it would break a framebuffer set up by GRUB.
+pub fn set_mode(mode: TextMode) {
+    {
+        //nothing may be written to the VGA buffer while the font is loaded through it
+        let _active = CONSOLES[active_console()].lock();
+        ::vga_mode::set_mode(mode);
+    }
+    resize(mode.columns(), mode.rows());
+}
+
+fn resize(width: usize, height: usize) {
+    for console in CONSOLES.iter() {
+        console.lock().resize(width, height);
+    }
+}
+
+/// Sets the text mode given by the `vga` option of the command line, e.g.
+/// `vga=80x50`, or sizes the consoles to the mode GRUB left the card in.
+/// Does nothing if GRUB set a graphics mode, `set gfxpayload=text` in
+/// grub.cfg keeps the text mode.
+pub fn init(multiboot_information_address: usize) {
+    use framebuffer::{self, FramebufferType};
+    use log::{self, Level};
+
+    if let Some(info) = framebuffer::framebuffer_info(multiboot_information_address) {
+        if info.framebuffer_type != FramebufferType::Text {
+            if let Some(option) = ::cmdline::option("vga") {
+                //the logging macros are defined after this module
+                log::log(Level::Warn, module_path!(),
+                         format_args!("vga={} is ignored, GRUB set a graphics mode", option));
+            }
+            return;
+        }
+    }
+
+    if let Some(option) = ::cmdline::option("vga") {
+        match option.parse() {
+            Ok(mode) => return set_mode(mode),
+            Err(()) => log::log(Level::Warn, module_path!(), format_args!("unknown text mode {}", option)),
+        }
+    }
+    let (columns, rows) = ::vga_mode::current_size();
+    resize(::core::cmp::min(columns, MAX_WIDTH), ::core::cmp::min(rows, MAX_HEIGHT));
+}
+
+//TODO bind to Shift+PageUp and Shift+PageDown once there is a keyboard driver
+pub fn scroll_up(lines: usize) {
+    CONSOLES[active_console()].lock().scroll_up(lines);
+}
+
+pub fn scroll_down(lines: usize) {
+    CONSOLES[active_console()].lock().scroll_down(lines);
+}
+
+pub fn active_console() -> usize {
+    ACTIVE_CONSOLE.load(Ordering::SeqCst)
+}
+
+/// Shows the virtual console `index` on the screen. The other consoles keep
+/// writing to their off-screen buffers. Returns false if there is no such
+/// console.
+//TODO bind to Alt+F1..F4 once there is a keyboard driver
+pub fn switch_console(index: usize) -> bool {
+    if index >= CONSOLE_COUNT {
+        return false;
+    }
+
+    let old = ACTIVE_CONSOLE.load(Ordering::SeqCst);
+    if old != index {
+        //the old console stops drawing before the new index is published, so
+        //that code following the index never finds two consoles on the screen
+        CONSOLES[old].lock().set_active(false);
+        ACTIVE_CONSOLE.store(index, Ordering::SeqCst);
+        CONSOLES[index].lock().set_active(true);
+    }
+    true
+}
+
+//colors of the panic screen
+const PANIC_COLOR: ColorCode = ColorCode::new(Color::White, Color::Red);
+
+/// Writes straight into the VGA buffer in white on red, without the locks
+/// of the consoles, which the code that panicked may hold. Only for the
+/// panic screen: the consoles don't know about this output.
+pub struct PanicWriter {
+    row: usize,
+    column: usize,
+    width: usize,
+    height: usize,
+}
+
+impl PanicWriter {
+    /// Clears the screen to red, hides the cursor and starts at the top.
+    pub fn new() -> PanicWriter {
+        let mut writer = PanicWriter::last_line();
+        for row in 0..writer.height {
+            for col in 0..writer.width {
+                writer.put_cell(row, col, b' ');
+            }
+        }
+        writer.row = 0;
+        crtc_write(CURSOR_START, crtc_read(CURSOR_START) | CURSOR_DISABLE);
+        writer
+    }
+
+    /// Writes over the last line of the screen and keeps the rest.
+    pub fn last_line() -> PanicWriter {
+        //the consoles may be locked, so the size is read from the card
+        let (columns, rows) = ::vga_mode::current_size();
+        let height = ::core::cmp::min(rows, MAX_HEIGHT);
+        PanicWriter {
+            row: height.saturating_sub(1),
+            column: 0,
+            width: ::core::cmp::min(columns, MAX_WIDTH),
+            height: height,
+        }
+    }
+
+    fn put_cell(&mut self, row: usize, col: usize, glyph: u8) {
+        let character = ScreenChar { ascii_character: glyph, color_code: PANIC_COLOR };
+        unsafe {
+            let buffer = 0xb8000 as *mut ScreenChar;
+            ::core::ptr::write_volatile(buffer.offset((row * self.width + col) as isize), character);
+        }
+    }
+
+    //no scrolling, what doesn't fit on the screen is dropped
+    fn put_glyph(&mut self, glyph: u8) {
+        if self.column >= self.width {
+            self.row += 1;
+            self.column = 0;
+        }
+        if self.row < self.height {
+            let (row, col) = (self.row, self.column);
+            self.put_cell(row, col, glyph);
+            self.column += 1;
+        }
+    }
+}
+
+impl fmt::Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            match c {
+                '\n' => {
+                    self.row += 1;
+                    self.column = 0;
+                }
+                c => self.put_glyph(::cp437::glyph(c)),
+            }
+        }
+        Ok(())
+    }
+}
+
 pub fn clear_screen() {
-    for _ in 0..BUFFER_HEIGHT {
+    let height = CONSOLES[active_console()].lock().height;
+    for _ in 0..height {
         println!("");
     }
 }
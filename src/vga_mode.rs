@@ -0,0 +1,271 @@
+// VGA text mode switching
+// the BIOS leaves the card in 80x25 (720x400 pixels, 9x16 character cells); the other modes
+// are set by writing the whole register set: miscellaneous output, sequencer, CRT controller,
+// graphics controller and attribute controller (register dumps from the standard modes)
+// the number of text lines is the number of scanlines divided by the font height, so the
+// 50 and 60 line modes need an 8x8 font, which is loaded into plane 2 of the video memory
+
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::instructions::port::{inb, outb};
+use psf::Font;
+
+static FONT_8X8: &'static [u8] = include_bytes!("fonts/default-8x8.psf");
+static FONT_8X16: &'static [u8] = include_bytes!("fonts/default-8x16.psf");
+
+const MISC_WRITE: u16 = 0x3c2;
+const SEQUENCER_INDEX: u16 = 0x3c4;
+const SEQUENCER_DATA: u16 = 0x3c5;
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const GRAPHICS_INDEX: u16 = 0x3ce;
+const GRAPHICS_DATA: u16 = 0x3cf;
+// index and data are written to the same port, reading INPUT_STATUS resets it to index
+const ATTRIBUTE_WRITE: u16 = 0x3c0;
+const INPUT_STATUS: u16 = 0x3da;
+
+// the glyphs of font 0 start at the beginning of plane 2, every glyph has 32 bytes
+const FONT_MEMORY: usize = 0xb8000;
+const GLYPH_STRIDE: usize = 32;
+
+// the BIOS font is in plane 2 until the first font is loaded
+static BIOS_FONT: AtomicBool = AtomicBool::new(true);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextMode {
+    Text80x25,
+    Text80x50,
+    Text90x30,
+    Text90x60,
+}
+
+impl TextMode {
+    pub fn columns(&self) -> usize {
+        match *self {
+            TextMode::Text80x25 | TextMode::Text80x50 => 80,
+            TextMode::Text90x30 | TextMode::Text90x60 => 90,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        match *self {
+            TextMode::Text80x25 => 25,
+            TextMode::Text80x50 => 50,
+            TextMode::Text90x30 => 30,
+            TextMode::Text90x60 => 60,
+        }
+    }
+
+    fn font_height(&self) -> usize {
+        match *self {
+            TextMode::Text80x25 | TextMode::Text90x30 => 16,
+            TextMode::Text80x50 | TextMode::Text90x60 => 8,
+        }
+    }
+
+    fn registers(&self) -> &'static Registers {
+        match *self {
+            TextMode::Text80x25 => &TEXT_80X25,
+            TextMode::Text80x50 => &TEXT_80X50,
+            TextMode::Text90x30 => &TEXT_90X30,
+            TextMode::Text90x60 => &TEXT_90X60,
+        }
+    }
+}
+
+// parses the names used on the command line, e.g. `80x50`
+impl FromStr for TextMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<TextMode, ()> {
+        match s {
+            "80x25" => Ok(TextMode::Text80x25),
+            "80x50" => Ok(TextMode::Text80x50),
+            "90x30" => Ok(TextMode::Text90x30),
+            "90x60" => Ok(TextMode::Text90x60),
+            _ => Err(()),
+        }
+    }
+}
+
+struct Registers {
+    misc: u8,
+    sequencer: [u8; 5],
+    crtc: [u8; 25],
+    graphics: [u8; 9],
+    attribute: [u8; 21],
+}
+
+// the graphics controller and the palette are the same in all text modes, only the pixel
+// panning (attribute register 0x13) depends on the character width
+const GRAPHICS: [u8; 9] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0e, 0x00, 0xff];
+const ATTRIBUTE_9_DOT: [u8; 21] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+    0x0c, 0x00, 0x0f, 0x08, 0x00,
+];
+const ATTRIBUTE_8_DOT: [u8; 21] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e, 0x3f,
+    0x0c, 0x00, 0x0f, 0x00, 0x00,
+];
+
+// 720x400, 9 pixel wide characters
+static TEXT_80X25: Registers = Registers {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f, 0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00, 0x50,
+        0x9c, 0x0e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3, 0xff,
+    ],
+    graphics: GRAPHICS,
+    attribute: ATTRIBUTE_9_DOT,
+};
+
+// the timing of 80x25 with 8 scanlines per character
+static TEXT_80X50: Registers = Registers {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5f, 0x4f, 0x50, 0x82, 0x55, 0x81, 0xbf, 0x1f, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x01, 0x40,
+        0x9c, 0x8e, 0x8f, 0x28, 0x1f, 0x96, 0xb9, 0xa3, 0xff,
+    ],
+    graphics: GRAPHICS,
+    attribute: ATTRIBUTE_9_DOT,
+};
+
+// 720x480 with the 28 MHz clock, 8 pixel wide characters
+static TEXT_90X30: Registers = Registers {
+    misc: 0xe7,
+    sequencer: [0x03, 0x01, 0x03, 0x00, 0x02],
+    crtc: [
+        0x6b, 0x59, 0x5a, 0x82, 0x60, 0x8d, 0x0b, 0x3e, 0x00, 0x4f, 0x0d, 0x0e, 0x00, 0x00, 0x00, 0x00,
+        0xea, 0x0c, 0xdf, 0x2d, 0x10, 0xe8, 0x05, 0xa3, 0xff,
+    ],
+    graphics: GRAPHICS,
+    attribute: ATTRIBUTE_8_DOT,
+};
+
+static TEXT_90X60: Registers = Registers {
+    misc: 0xe7,
+    sequencer: [0x03, 0x01, 0x03, 0x00, 0x02],
+    crtc: [
+        0x6b, 0x59, 0x5a, 0x82, 0x60, 0x8d, 0x0b, 0x3e, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x00, 0x00,
+        0xea, 0x0c, 0xdf, 0x2d, 0x08, 0xe8, 0x05, 0xa3, 0xff,
+    ],
+    graphics: GRAPHICS,
+    attribute: ATTRIBUTE_8_DOT,
+};
+
+fn write_indexed(index_port: u16, data_port: u16, index: u8, value: u8) {
+    unsafe {
+        outb(index_port, index);
+        outb(data_port, value);
+    }
+}
+
+fn read_indexed(index_port: u16, data_port: u16, index: u8) -> u8 {
+    unsafe {
+        outb(index_port, index);
+        inb(data_port)
+    }
+}
+
+fn write_registers(registers: &Registers) {
+    unsafe { outb(MISC_WRITE, registers.misc) };
+    for (i, &value) in registers.sequencer.iter().enumerate() {
+        write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, i as u8, value);
+    }
+
+    // registers 0 to 7 are write protected by bit 7 of register 0x11, bit 7 of register 3 has
+    // to stay set for compatibility
+    let end_horizontal_blanking = read_indexed(CRTC_INDEX, CRTC_DATA, 0x03);
+    write_indexed(CRTC_INDEX, CRTC_DATA, 0x03, end_horizontal_blanking | 0x80);
+    let vertical_retrace_end = read_indexed(CRTC_INDEX, CRTC_DATA, 0x11);
+    write_indexed(CRTC_INDEX, CRTC_DATA, 0x11, vertical_retrace_end & !0x80);
+    for (i, &value) in registers.crtc.iter().enumerate() {
+        let value = match i {
+            0x03 => value | 0x80,
+            0x11 => value & !0x80,
+            _ => value,
+        };
+        write_indexed(CRTC_INDEX, CRTC_DATA, i as u8, value);
+    }
+
+    for (i, &value) in registers.graphics.iter().enumerate() {
+        write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, i as u8, value);
+    }
+
+    unsafe {
+        for (i, &value) in registers.attribute.iter().enumerate() {
+            inb(INPUT_STATUS);
+            outb(ATTRIBUTE_WRITE, i as u8);
+            outb(ATTRIBUTE_WRITE, value);
+        }
+        // bit 5 gives the palette back to the display, without it the screen stays blank
+        inb(INPUT_STATUS);
+        outb(ATTRIBUTE_WRITE, 0x20);
+    }
+}
+
+// writes the glyphs to plane 2, which is only reachable with the planes addressed one by one
+// (instead of odd/even addressing of the characters and attributes)
+fn load_font(font: &Font) {
+    let map_mask = read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x02);
+    let memory_mode = read_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x04);
+    let read_map = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x04);
+    let mode = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x05);
+    let misc = read_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x06);
+
+    // sequential addressing, only plane 2 is written
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x04, memory_mode | 0x04);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x05, mode & !0x10);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x06, misc & !0x02);
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x02, 1 << 2);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x04, 2);
+
+    for glyph in 0..256 {
+        let bitmap = font.glyph(glyph);
+        let cell = (FONT_MEMORY + glyph * GLYPH_STRIDE) as *mut u8;
+        for row in 0..GLYPH_STRIDE {
+            // one byte per row, the rows below the glyph are cleared
+            let bits = if row < font.height { bitmap[row] } else { 0 };
+            unsafe { ::core::ptr::write_volatile(cell.offset(row as isize), bits) };
+        }
+    }
+
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x02, map_mask);
+    write_indexed(SEQUENCER_INDEX, SEQUENCER_DATA, 0x04, memory_mode);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x04, read_map);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x05, mode);
+    write_indexed(GRAPHICS_INDEX, GRAPHICS_DATA, 0x06, misc);
+}
+
+/// The size (columns, rows) of the text mode the card is in, read from the
+/// CRT controller.
+pub fn current_size() -> (usize, usize) {
+    let columns = read_indexed(CRTC_INDEX, CRTC_DATA, 0x01) as usize + 1;
+    // bits 8 and 9 of the vertical display end are in the overflow register
+    let overflow = read_indexed(CRTC_INDEX, CRTC_DATA, 0x07) as usize;
+    let vertical_display_end = read_indexed(CRTC_INDEX, CRTC_DATA, 0x12) as usize
+        | (overflow >> 1 & 1) << 8 | (overflow >> 6 & 1) << 9;
+    let scanlines = (read_indexed(CRTC_INDEX, CRTC_DATA, 0x09) & 0x1f) as usize + 1;
+    (columns, (vertical_display_end + 1) / scanlines)
+}
+
+/// Programs the VGA registers for the text mode and loads a font of the
+/// right height. The text buffer has to be redrawn afterwards, loading the
+/// font goes through the same memory. Must not be called in graphics modes.
+pub fn set_mode(mode: TextMode) {
+    write_registers(mode.registers());
+
+    // the 16 line modes keep the BIOS font as long as it wasn't overwritten
+    match mode.font_height() {
+        8 => {
+            load_font(&Font::parse(FONT_8X8).expect("invalid 8x8 font"));
+            BIOS_FONT.store(false, Ordering::Relaxed);
+        }
+        _ if !BIOS_FONT.load(Ordering::Relaxed) => {
+            load_font(&Font::parse(FONT_8X16).expect("invalid 8x16 font"));
+        }
+        _ => {}
+    }
+}